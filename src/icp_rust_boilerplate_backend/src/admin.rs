@@ -0,0 +1,173 @@
+// Canister administration and moderation.
+//
+// Admins are kept in `ADMINS`. The principal installing the canister is seeded
+// as the first admin, and every controller of the canister counts as an admin
+// whether or not it is listed. Admins can hide events from everyone but their
+// staff, force-delete them and ban principals from creating events. Every
+// moderation action is appended to `MODERATION_LOG`, keyed by a sequence number.
+
+use candid::{CandidType, Decode, Encode, Principal};
+use ic_stable_structures::{BoundedStorable, Storable};
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::ops::Bound;
+
+use crate::{Error, StorablePrincipal, ADMINS, BANNED, MODERATION_LOG};
+
+// Longest reason, in bytes, an admin may give for an action
+pub const MAX_REASON_LEN: usize = 500;
+
+// Largest page size served by list_moderation_actions
+const MAX_LOG_PAGE: usize = 100;
+
+#[derive(CandidType, Clone, Serialize, Deserialize)]
+pub enum ModerationKind {
+    HideEvent { id: u64 },
+    UnhideEvent { id: u64 },
+    ForceDeleteEvent { id: u64 },
+    BanPrincipal { principal: Principal },
+    UnbanPrincipal { principal: Principal },
+    AddAdmin { principal: Principal },
+    RemoveAdmin { principal: Principal },
+}
+
+// An entry of the moderation log
+#[derive(CandidType, Clone, Serialize, Deserialize)]
+pub struct ModerationAction {
+    pub seq: u64,
+    pub admin: Principal,
+    pub kind: ModerationKind,
+    pub reason: Option<String>,
+    pub at: u64,
+}
+
+// Page of the moderation log returned by list_moderation_actions
+#[derive(CandidType, Serialize, Deserialize)]
+pub struct ModerationPage {
+    pub actions: Vec<ModerationAction>,
+    // Sequence number to continue after
+    pub next_cursor: Option<u64>,
+}
+
+impl Storable for ModerationAction {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for ModerationAction {
+    const MAX_SIZE: u32 = 1024;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+// Whether `principal` may use the admin endpoints
+pub fn is_admin(principal: Principal) -> bool {
+    ADMINS.with(|a| a.borrow().contains_key(&StorablePrincipal(principal)))
+        || ic_cdk::api::is_controller(&principal)
+}
+
+// Adds `principal` to the admin set; returns false if they already were in it
+pub fn add_admin(principal: Principal, now: u64) -> bool {
+    ADMINS
+        .with(|a| a.borrow_mut().insert(StorablePrincipal(principal), now))
+        .is_none()
+}
+
+// Removes `principal` from the admin set; returns false if they were not in it
+pub fn remove_admin(principal: Principal) -> bool {
+    ADMINS
+        .with(|a| a.borrow_mut().remove(&StorablePrincipal(principal)))
+        .is_some()
+}
+
+// Every listed admin; controllers are admins without being listed
+pub fn admins() -> Vec<Principal> {
+    ADMINS.with(|a| a.borrow().iter().map(|(principal, _)| principal.0).collect())
+}
+
+// Seeds the admin set with `principal` unless it already has members
+pub fn seed(principal: Principal, now: u64) {
+    if ADMINS.with(|a| a.borrow().is_empty()) && principal != Principal::anonymous() {
+        add_admin(principal, now);
+    }
+}
+
+// Whether `principal` is banned from creating events
+pub fn is_banned(principal: Principal) -> bool {
+    BANNED.with(|b| b.borrow().contains_key(&StorablePrincipal(principal)))
+}
+
+// Bans `principal` from creating events; returns false if they already were banned
+pub fn ban(principal: Principal, now: u64) -> bool {
+    BANNED
+        .with(|b| b.borrow_mut().insert(StorablePrincipal(principal), now))
+        .is_none()
+}
+
+// Lifts the ban on `principal`; returns false if they were not banned
+pub fn unban(principal: Principal) -> bool {
+    BANNED
+        .with(|b| b.borrow_mut().remove(&StorablePrincipal(principal)))
+        .is_some()
+}
+
+// Checks the length of a reason given for a moderation action
+pub fn validate_reason(reason: &Option<String>) -> Result<(), Error> {
+    match reason {
+        Some(reason) if reason.len() > MAX_REASON_LEN => Err(Error::invalid_field(
+            "reason",
+            format!("must be at most {} bytes", MAX_REASON_LEN),
+        )),
+        _ => Ok(()),
+    }
+}
+
+// Appends an action to the moderation log
+pub fn record(admin: Principal, kind: ModerationKind, reason: Option<String>, now: u64) {
+    MODERATION_LOG.with(|log| {
+        let mut log = log.borrow_mut();
+        let seq = log.last_key_value().map_or(0, |(seq, _)| seq + 1);
+        log.insert(
+            seq,
+            ModerationAction {
+                seq,
+                admin,
+                kind,
+                reason,
+                at: now,
+            },
+        );
+    });
+}
+
+// Lists the moderation log oldest first, starting after sequence number `cursor`
+pub fn page(cursor: Option<u64>, limit: u32) -> ModerationPage {
+    let start = match cursor {
+        Some(seq) => Bound::Excluded(seq),
+        None => Bound::Unbounded,
+    };
+    let limit = (limit as usize).clamp(1, MAX_LOG_PAGE);
+    let mut actions: Vec<ModerationAction> = MODERATION_LOG.with(|log| {
+        log.borrow()
+            .range((start, Bound::Unbounded))
+            .take(limit + 1)
+            .map(|(_, action)| action)
+            .collect()
+    });
+
+    // An extra entry means there is at least one more page
+    let next_cursor = if actions.len() > limit {
+        actions.truncate(limit);
+        actions.last().map(|action| action.seq)
+    } else {
+        None
+    };
+    ModerationPage {
+        actions,
+        next_cursor,
+    }
+}
@@ -0,0 +1,708 @@
+// Attendance bookkeeping.
+//
+// Attendees are stored per slot: `(event id, occurrence start)`, where the
+// occurrence start is `WHOLE_EVENT` for one-off events and the start time of the
+// chosen occurrence for recurring events. `SLOT_COUNTS` caches the number of
+// attendees of every slot.
+//
+// Once a slot reaches the event's capacity, further callers join the slot's
+// waitlist. `WAITLIST` keeps it ordered by a global sequence number and
+// `WAITLIST_ENTRIES` maps each waiting principal back to its sequence number.
+//
+// `RSVPS` records every principal's answer for a slot with its timestamps. Only
+// `Going` holds a seat in `ATTENDEES` and only `Waitlisted` holds a waitlist
+// entry; the other states are plain records.
+//
+// `CHECK_INS` records when an attendee was checked in at the door.
+//
+// `SEATS_BY_PRINCIPAL` mirrors `ATTENDEES` keyed by principal first, so the
+// slots a principal holds a seat in can be listed without a full scan.
+
+use candid::{CandidType, Principal};
+use ic_stable_structures::{BoundedStorable, Storable};
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::ops::Bound;
+
+use crate::{
+    recurrence, Error, Event, StorablePrincipal, ATTENDEES, CHECK_INS, RSVPS,
+    SEATS_BY_PRINCIPAL, SLOT_COUNTS, WAITLIST, WAITLIST_ENTRIES, WAITLIST_SEQ,
+};
+
+// Occurrence component of the slot of a one-off event
+pub const WHOLE_EVENT: u64 = 0;
+
+// Largest page size served by get_event_attendees and get_event_waitlist
+const MAX_ATTENDEE_PAGE: usize = 500;
+
+// Most principals waiting for a single slot
+const MAX_WAITLIST: usize = 1_000;
+
+// (event id, occurrence start)
+pub type Slot = (u64, u64);
+
+// Attendee entries hold the time the attendee joined
+pub type AttendeeKey = (Slot, StorablePrincipal);
+
+// Waitlist entries are keyed by (slot, sequence number) and hold the waiting
+// principal and the time they joined
+pub type WaitlistKey = (Slot, u64);
+
+// Page of attendees returned by get_event_attendees
+#[derive(CandidType, Serialize, Deserialize)]
+pub struct AttendeePage {
+    pub attendees: Vec<Principal>,
+    pub next_cursor: Option<Principal>,
+}
+
+#[derive(CandidType, Serialize, Deserialize)]
+pub struct WaitlistEntry {
+    pub principal: Principal,
+    // 1-based position in the waitlist
+    pub position: u64,
+    pub joined_at: u64,
+}
+
+// Page of the waitlist returned by get_event_waitlist
+#[derive(CandidType, Serialize, Deserialize)]
+pub struct WaitlistPage {
+    pub entries: Vec<WaitlistEntry>,
+    // Number of entries to skip to read the next page
+    pub next_cursor: Option<u64>,
+}
+
+#[derive(CandidType, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub enum RsvpStatus {
+    Going,
+    // Going, but the event was full when they answered
+    Waitlisted,
+    Maybe,
+    NotGoing,
+    // Set by the event owner; the principal cannot RSVP to the slot again
+    DeclinedByHost,
+}
+
+// A principal's answer for a slot
+#[derive(CandidType, Clone, Copy, Serialize, Deserialize)]
+pub struct Rsvp {
+    pub status: RsvpStatus,
+    // When the principal first answered
+    pub responded_at: u64,
+    // When the status last changed
+    pub updated_at: u64,
+}
+
+impl RsvpStatus {
+    fn to_byte(self) -> u8 {
+        self as u8
+    }
+
+    fn from_byte(byte: u8) -> Self {
+        match byte {
+            0 => Self::Going,
+            1 => Self::Waitlisted,
+            2 => Self::Maybe,
+            3 => Self::NotGoing,
+            _ => Self::DeclinedByHost,
+        }
+    }
+}
+
+// Stored as | status (1 byte) | responded_at (u64 BE) | updated_at (u64 BE) |
+impl Storable for Rsvp {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        let mut bytes = Vec::with_capacity(Self::MAX_SIZE as usize);
+        bytes.push(self.status.to_byte());
+        bytes.extend_from_slice(&self.responded_at.to_be_bytes());
+        bytes.extend_from_slice(&self.updated_at.to_be_bytes());
+        Cow::Owned(bytes)
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        let timestamp = |at: usize| u64::from_be_bytes(bytes[at..at + 8].try_into().unwrap());
+        Self {
+            status: RsvpStatus::from_byte(bytes[0]),
+            responded_at: timestamp(1),
+            updated_at: timestamp(9),
+        }
+    }
+}
+
+impl BoundedStorable for Rsvp {
+    const MAX_SIZE: u32 = 17;
+    const IS_FIXED_SIZE: bool = true;
+}
+
+// Smallest principal, used as the lower end of key ranges
+fn first_principal() -> StorablePrincipal {
+    StorablePrincipal(Principal::from_slice(&[]))
+}
+
+// Key range covering every attendee of a slot
+fn slot_range(slot: Slot) -> (Bound<AttendeeKey>, Bound<AttendeeKey>) {
+    let start = Bound::Included((slot, first_principal()));
+    match slot.1.checked_add(1) {
+        Some(next) => (start, Bound::Excluded(((slot.0, next), first_principal()))),
+        None => (start, Bound::Excluded(((slot.0.saturating_add(1), 0), first_principal()))),
+    }
+}
+
+// Key range covering every attendee of every slot of an event
+fn event_range(id: u64) -> (Bound<AttendeeKey>, Bound<AttendeeKey>) {
+    let start = Bound::Included(((id, 0), first_principal()));
+    match id.checked_add(1) {
+        Some(next) => (start, Bound::Excluded(((next, 0), first_principal()))),
+        None => (start, Bound::Unbounded),
+    }
+}
+
+// Resolves the slot an attendance request targets; recurring events need a valid occurrence
+pub fn resolve_slot(event: &Event, occurrence: Option<u64>) -> Result<Slot, Error> {
+    match (&event.recurrence, event.starts_at, occurrence) {
+        (Some(rule), Some(first), Some(start)) => {
+            if recurrence::is_occurrence(rule, first, start) {
+                Ok((event.id, start))
+            } else {
+                Err(Error::invalid_field(
+                    "occurrence",
+                    format!("event with id={} has no occurrence at {}", event.id, start),
+                ))
+            }
+        }
+        (Some(_), _, None) => Err(Error::invalid_field(
+            "occurrence",
+            format!("event with id={} is recurring; pick an occurrence", event.id),
+        )),
+        (None, starts_at, Some(start)) if starts_at != Some(start) => Err(Error::invalid_field(
+            "occurrence",
+            format!("event with id={} has no occurrence at {}", event.id, start),
+        )),
+        _ => Ok((event.id, WHOLE_EVENT)),
+    }
+}
+
+// Number of attendees of a slot
+pub fn count(slot: Slot) -> u64 {
+    SLOT_COUNTS.with(|c| c.borrow().get(&slot).unwrap_or(0))
+}
+
+fn set_count(slot: Slot, count: u64) {
+    SLOT_COUNTS.with(|c| {
+        let mut c = c.borrow_mut();
+        if count == 0 {
+            c.remove(&slot);
+        } else {
+            c.insert(slot, count);
+        }
+    });
+}
+
+// Whether `principal` holds a seat in `slot`
+pub fn is_attending(slot: Slot, principal: Principal) -> bool {
+    ATTENDEES.with(|a| a.borrow().contains_key(&(slot, StorablePrincipal(principal))))
+}
+
+// End of the occurrence a slot stands for; None for unscheduled events
+pub fn slot_ends_at(event: &Event, slot: Slot) -> Option<u64> {
+    let (starts_at, ends_at) = (event.starts_at?, event.ends_at?);
+    if slot.1 == WHOLE_EVENT {
+        return Some(ends_at);
+    }
+    Some(slot.1.saturating_add(ends_at.saturating_sub(starts_at)))
+}
+
+// Whether a slot is at (or above) the event's capacity
+pub fn is_full(slot: Slot, capacity: Option<u32>) -> bool {
+    capacity.is_some_and(|capacity| count(slot) >= capacity as u64)
+}
+
+// The principal's answer for a slot, if they gave one
+pub fn rsvp(slot: Slot, principal: Principal) -> Option<Rsvp> {
+    RSVPS.with(|r| r.borrow().get(&(slot, StorablePrincipal(principal))))
+}
+
+// Records a new status, keeping the time of the principal's first answer
+pub fn set_rsvp(slot: Slot, principal: Principal, status: RsvpStatus, now: u64) -> Rsvp {
+    let responded_at = rsvp(slot, principal).map_or(now, |rsvp| rsvp.responded_at);
+    let rsvp = Rsvp {
+        status,
+        responded_at,
+        updated_at: now,
+    };
+    RSVPS.with(|r| r.borrow_mut().insert((slot, StorablePrincipal(principal)), rsvp));
+    rsvp
+}
+
+// Records `principal` as attending `slot`; returns false if they already were
+pub fn add(slot: Slot, principal: Principal, joined_at: u64) -> bool {
+    let previous = ATTENDEES.with(|a| {
+        a.borrow_mut()
+            .insert((slot, StorablePrincipal(principal)), joined_at)
+    });
+    if previous.is_some() {
+        return false;
+    }
+    SEATS_BY_PRINCIPAL.with(|s| s.borrow_mut().insert((StorablePrincipal(principal), slot), ()));
+    set_count(slot, count(slot) + 1);
+    set_rsvp(slot, principal, RsvpStatus::Going, joined_at);
+    true
+}
+
+// Gives up the seat or waitlist entry `principal` holds in `slot`, promoting the
+// head of the waitlist into a freed seat and keeping `attendee_count` in step;
+// returns false if they held neither
+pub fn release(event: &mut Event, slot: Slot, principal: Principal, now: u64) -> bool {
+    let removed = ATTENDEES.with(|a| a.borrow_mut().remove(&(slot, StorablePrincipal(principal))));
+    if removed.is_none() {
+        return leave_waitlist(slot, principal);
+    }
+    SEATS_BY_PRINCIPAL.with(|s| s.borrow_mut().remove(&(StorablePrincipal(principal), slot)));
+    CHECK_INS.with(|c| c.borrow_mut().remove(&(slot, StorablePrincipal(principal))));
+    set_count(slot, count(slot).saturating_sub(1));
+    event.attendee_count = event.attendee_count.saturating_sub(1);
+    event.attendee_count += promote(slot, event.capacity, now);
+    true
+}
+
+// Records that `principal`, who holds a seat in `slot`, arrived; returns the check-in time
+pub fn check_in(slot: Slot, principal: Principal, now: u64) -> Result<u64, Error> {
+    if !is_attending(slot, principal) {
+        return Err(Error::NotFound {
+            msg: format!("{} is not attending the event with id={}", principal, slot.0),
+        });
+    }
+    let key = (slot, StorablePrincipal(principal));
+    if let Some(checked_in_at) = CHECK_INS.with(|c| c.borrow().get(&key)) {
+        return Err(Error::AlreadyExists {
+            msg: format!("{} was already checked in at {}", principal, checked_in_at),
+        });
+    }
+    CHECK_INS.with(|c| c.borrow_mut().insert(key, now));
+    Ok(now)
+}
+
+// Slots `principal` holds a seat in, by event id and occurrence, passed through `load`;
+// at most `limit` of the loaded values are returned, and slots `load` skips do not count
+pub fn seats_of<T>(
+    principal: Principal,
+    limit: usize,
+    mut load: impl FnMut(Slot) -> Option<T>,
+) -> Vec<T> {
+    let key = StorablePrincipal(principal);
+    SEATS_BY_PRINCIPAL.with(|s| {
+        s.borrow()
+            .range((key, (0, 0))..=(key, (u64::MAX, u64::MAX)))
+            .filter_map(|((_, slot), _)| load(slot))
+            .take(limit)
+            .collect()
+    })
+}
+
+// Seats of an event with the time they were taken, by slot and principal, starting
+// after the seat `after` and at most `limit` of them
+pub fn seats_of_event(
+    id: u64,
+    after: Option<(u64, Principal)>,
+    limit: usize,
+) -> Vec<(Slot, Principal, u64)> {
+    let (start, end) = event_range(id);
+    let start = match after {
+        Some((occurrence, principal)) => {
+            Bound::Excluded(((id, occurrence), StorablePrincipal(principal)))
+        }
+        None => start,
+    };
+    ATTENDEES.with(|a| {
+        a.borrow()
+            .range((start, end))
+            .take(limit)
+            .map(|((slot, principal), joined_at)| (slot, principal.0, joined_at))
+            .collect()
+    })
+}
+
+// Adds the seats of an event to `SEATS_BY_PRINCIPAL`; used by the v11 migration
+pub fn index_seats(id: u64) {
+    let seats: Vec<AttendeeKey> =
+        ATTENDEES.with(|a| a.borrow().range(event_range(id)).map(|(k, _)| k).collect());
+    SEATS_BY_PRINCIPAL.with(|s| {
+        let mut s = s.borrow_mut();
+        for (slot, principal) in seats {
+            s.insert((principal, slot), ());
+        }
+    });
+}
+
+// Lists the attendees of a slot a page at a time, starting after `cursor`
+pub fn page(slot: Slot, cursor: Option<Principal>, limit: u32) -> AttendeePage {
+    let mut range = slot_range(slot);
+    if let Some(cursor) = cursor {
+        range.0 = Bound::Excluded((slot, StorablePrincipal(cursor)));
+    }
+
+    let limit = (limit as usize).clamp(1, MAX_ATTENDEE_PAGE);
+    let mut attendees: Vec<Principal> = ATTENDEES.with(|a| {
+        a.borrow()
+            .range(range)
+            .take(limit + 1)
+            .map(|((_, principal), _)| principal.0)
+            .collect()
+    });
+
+    // An extra entry means there is at least one more page
+    let next_cursor = if attendees.len() > limit {
+        attendees.truncate(limit);
+        attendees.last().copied()
+    } else {
+        None
+    };
+
+    AttendeePage {
+        attendees,
+        next_cursor,
+    }
+}
+
+// Key range covering the whole waitlist of a slot
+fn waitlist_range(slot: Slot) -> (Bound<WaitlistKey>, Bound<WaitlistKey>) {
+    (Bound::Included((slot, 0)), Bound::Included((slot, u64::MAX)))
+}
+
+// 1-based waitlist position of `principal`, if they are waiting for `slot`
+pub fn waitlist_position(slot: Slot, principal: Principal) -> Option<u64> {
+    let seq = WAITLIST_ENTRIES.with(|w| w.borrow().get(&(slot, StorablePrincipal(principal))))?;
+    let ahead = WAITLIST.with(|w| w.borrow().range((slot, 0)..(slot, seq)).count());
+    Some(ahead as u64 + 1)
+}
+
+// Number of principals waiting for `slot`
+pub fn waitlist_len(slot: Slot) -> usize {
+    WAITLIST.with(|w| w.borrow().range(waitlist_range(slot)).count())
+}
+
+// Whether another principal may join the waitlist of `slot`
+pub fn has_waitlist_room(slot: Slot) -> bool {
+    waitlist_len(slot) < MAX_WAITLIST
+}
+
+// Appends `principal` to the waitlist of `slot` and returns their position
+pub fn join_waitlist(slot: Slot, principal: Principal, joined_at: u64) -> u64 {
+    if let Some(position) = waitlist_position(slot, principal) {
+        return position;
+    }
+    let seq = WAITLIST_SEQ
+        .with(|counter| {
+            let current_value = *counter.borrow().get();
+            counter.borrow_mut().set(current_value + 1)
+        })
+        .expect("cannot increment waitlist sequence");
+    WAITLIST.with(|w| {
+        w.borrow_mut()
+            .insert((slot, seq), (StorablePrincipal(principal), joined_at))
+    });
+    WAITLIST_ENTRIES.with(|w| w.borrow_mut().insert((slot, StorablePrincipal(principal)), seq));
+    set_rsvp(slot, principal, RsvpStatus::Waitlisted, joined_at);
+    waitlist_position(slot, principal).unwrap_or(1)
+}
+
+// Removes `principal` from the waitlist of `slot`; returns false if they were not on it
+pub fn leave_waitlist(slot: Slot, principal: Principal) -> bool {
+    let Some(seq) =
+        WAITLIST_ENTRIES.with(|w| w.borrow_mut().remove(&(slot, StorablePrincipal(principal))))
+    else {
+        return false;
+    };
+    WAITLIST.with(|w| w.borrow_mut().remove(&(slot, seq)));
+    true
+}
+
+// Moves principals from the head of the waitlist into the slot while it has free
+// seats; returns how many were promoted
+pub fn promote(slot: Slot, capacity: Option<u32>, now: u64) -> u64 {
+    let mut promoted = 0;
+    while !is_full(slot, capacity) {
+        let Some(((_, seq), (principal, _))) =
+            WAITLIST.with(|w| w.borrow().range(waitlist_range(slot)).next())
+        else {
+            break;
+        };
+        WAITLIST.with(|w| w.borrow_mut().remove(&(slot, seq)));
+        WAITLIST_ENTRIES.with(|w| w.borrow_mut().remove(&(slot, principal)));
+        if add(slot, principal.0, now) {
+            promoted += 1;
+        }
+    }
+    promoted
+}
+
+// Promotes waiting principals into every slot of an event that has free seats,
+// e.g. after its capacity was raised; returns how many were promoted
+pub fn promote_event(event: &Event, now: u64) -> u64 {
+    let mut slots: Vec<Slot> = WAITLIST.with(|w| {
+        w.borrow()
+            .range(((event.id, 0), 0)..=((event.id, u64::MAX), u64::MAX))
+            .map(|((slot, _), _)| slot)
+            .collect()
+    });
+    slots.dedup();
+    slots
+        .into_iter()
+        .map(|slot| promote(slot, event.capacity, now))
+        .sum()
+}
+
+// Lists the waitlist of a slot in order, skipping the first `cursor` entries
+pub fn waitlist_page(slot: Slot, cursor: Option<u64>, limit: u32) -> WaitlistPage {
+    let offset = cursor.unwrap_or(0);
+    let limit = (limit as usize).clamp(1, MAX_ATTENDEE_PAGE);
+    let mut entries: Vec<WaitlistEntry> = WAITLIST.with(|w| {
+        w.borrow()
+            .range(waitlist_range(slot))
+            .skip(offset as usize)
+            .take(limit + 1)
+            .enumerate()
+            .map(|(i, (_, (principal, joined_at)))| WaitlistEntry {
+                principal: principal.0,
+                position: offset + i as u64 + 1,
+                joined_at,
+            })
+            .collect()
+    });
+
+    // An extra entry means there is at least one more page
+    let next_cursor = if entries.len() > limit {
+        entries.truncate(limit);
+        Some(offset + limit as u64)
+    } else {
+        None
+    };
+    WaitlistPage {
+        entries,
+        next_cursor,
+    }
+}
+
+// Records a `Going` or `Waitlisted` answer for seats and waitlist entries of an
+// event that predate RSVP records; used by the v7 migration
+pub fn backfill_rsvps(id: u64) {
+    let attendees: Vec<(AttendeeKey, u64)> =
+        ATTENDEES.with(|a| a.borrow().range(event_range(id)).collect());
+    let waiting: Vec<(AttendeeKey, u64)> = WAITLIST.with(|w| {
+        w.borrow()
+            .range(((id, 0), 0)..=((id, u64::MAX), u64::MAX))
+            .map(|((slot, _), (principal, joined_at))| ((slot, principal), joined_at))
+            .collect()
+    });
+    for (statuses, status) in [(attendees, RsvpStatus::Going), (waiting, RsvpStatus::Waitlisted)] {
+        for ((slot, principal), at) in statuses {
+            if rsvp(slot, principal.0).is_none() {
+                set_rsvp(slot, principal.0, status, at);
+            }
+        }
+    }
+}
+
+// Removes every attendee entry, check-in, count, waitlist entry and RSVP of an event
+pub fn remove_event(id: u64) {
+    ATTENDEES.with(|a| {
+        let keys: Vec<AttendeeKey> = a.borrow().range(event_range(id)).map(|(k, _)| k).collect();
+        let mut a = a.borrow_mut();
+        for (slot, principal) in keys {
+            a.remove(&(slot, principal));
+            SEATS_BY_PRINCIPAL.with(|s| s.borrow_mut().remove(&(principal, slot)));
+        }
+    });
+    CHECK_INS.with(|c| {
+        let keys: Vec<AttendeeKey> = c.borrow().range(event_range(id)).map(|(k, _)| k).collect();
+        let mut c = c.borrow_mut();
+        for key in keys {
+            c.remove(&key);
+        }
+    });
+    RSVPS.with(|r| {
+        let keys: Vec<AttendeeKey> = r.borrow().range(event_range(id)).map(|(k, _)| k).collect();
+        let mut r = r.borrow_mut();
+        for key in keys {
+            r.remove(&key);
+        }
+    });
+    SLOT_COUNTS.with(|c| {
+        let slots: Vec<Slot> = c
+            .borrow()
+            .range((id, 0)..=(id, u64::MAX))
+            .map(|(slot, _)| slot)
+            .collect();
+        let mut c = c.borrow_mut();
+        for slot in slots {
+            c.remove(&slot);
+        }
+    });
+    let waiting: Vec<(WaitlistKey, StorablePrincipal)> = WAITLIST.with(|w| {
+        w.borrow()
+            .range(((id, 0), 0)..=((id, u64::MAX), u64::MAX))
+            .map(|(key, (principal, _))| (key, principal))
+            .collect()
+    });
+    for ((slot, seq), principal) in waiting {
+        WAITLIST.with(|w| w.borrow_mut().remove(&(slot, seq)));
+        WAITLIST_ENTRIES.with(|w| w.borrow_mut().remove(&(slot, principal)));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn principal(n: u32) -> Principal {
+        Principal::from_slice(&n.to_be_bytes())
+    }
+
+    fn event(capacity: Option<u32>) -> Event {
+        Event {
+            capacity,
+            ..crate::test_event(1)
+        }
+    }
+
+    #[test]
+    fn slot_is_full_exactly_at_capacity() {
+        let slot = (1, WHOLE_EVENT);
+        assert!(!is_full(slot, Some(2)));
+        add(slot, principal(1), 0);
+        assert!(!is_full(slot, Some(2)));
+        add(slot, principal(2), 0);
+        assert!(is_full(slot, Some(2)));
+        assert!(is_full(slot, Some(1)));
+        assert!(!is_full(slot, None));
+        // A second seat for the same principal is not counted
+        assert!(!add(slot, principal(2), 0));
+        assert_eq!(count(slot), 2);
+    }
+
+    #[test]
+    fn promotes_the_waitlist_in_joining_order() {
+        let mut event = event(Some(1));
+        let slot = (1, WHOLE_EVENT);
+        add(slot, principal(1), 0);
+        event.attendee_count = 1;
+        assert_eq!(join_waitlist(slot, principal(2), 1), 1);
+        assert_eq!(join_waitlist(slot, principal(3), 2), 2);
+        assert_eq!(join_waitlist(slot, principal(4), 3), 3);
+        // Joining again keeps the original position
+        assert_eq!(join_waitlist(slot, principal(3), 4), 2);
+
+        assert!(release(&mut event, slot, principal(1), 5));
+        assert_eq!(event.attendee_count, 1);
+        assert!(is_attending(slot, principal(2)));
+        assert!(rsvp(slot, principal(2)).is_some_and(|rsvp| rsvp.status == RsvpStatus::Going));
+        assert_eq!(waitlist_position(slot, principal(3)), Some(1));
+        assert_eq!(waitlist_position(slot, principal(4)), Some(2));
+
+        // Raising the capacity promotes everyone still waiting
+        event.capacity = Some(3);
+        assert_eq!(promote_event(&event, 6), 2);
+        assert_eq!(count(slot), 3);
+        assert_eq!(waitlist_len(slot), 0);
+    }
+
+    #[test]
+    fn rejects_joins_once_the_waitlist_is_full() {
+        let slot = (1, WHOLE_EVENT);
+        for n in 0..MAX_WAITLIST as u32 - 1 {
+            join_waitlist(slot, principal(n), 0);
+        }
+        assert!(has_waitlist_room(slot));
+        join_waitlist(slot, principal(MAX_WAITLIST as u32), 0);
+        assert!(!has_waitlist_room(slot));
+        // Other slots keep their own waitlist
+        assert!(has_waitlist_room((1, 10)));
+    }
+
+    #[test]
+    fn releasing_without_a_seat_changes_nothing() {
+        let mut event = event(Some(1));
+        let slot = (1, WHOLE_EVENT);
+        add(slot, principal(1), 0);
+        event.attendee_count = 1;
+        join_waitlist(slot, principal(2), 0);
+
+        assert!(!release(&mut event, slot, principal(3), 1));
+        assert!(!release(&mut event, (1, 10), principal(1), 1));
+        assert_eq!((count(slot), event.attendee_count), (1, 1));
+
+        // A waiting principal gives up their place without freeing a seat
+        assert!(release(&mut event, slot, principal(2), 1));
+        assert_eq!((count(slot), event.attendee_count, waitlist_len(slot)), (1, 1, 0));
+        assert!(!release(&mut event, slot, principal(2), 1));
+    }
+
+    const DAY: u64 = 86_400 * 1_000_000_000;
+
+    // An event repeating daily for three days, skipping the second
+    fn series() -> Event {
+        Event {
+            starts_at: Some(10 * DAY),
+            ends_at: Some(10 * DAY + 1),
+            recurrence: Some(recurrence::RecurrenceRule {
+                frequency: recurrence::Frequency::Daily,
+                interval: None,
+                by_day: Vec::new(),
+                count: Some(3),
+                until: None,
+                exceptions: vec![11 * DAY],
+            }),
+            ..crate::test_event(1)
+        }
+    }
+
+    #[test]
+    fn resolves_occurrences_of_a_series() {
+        let event = series();
+        for start in [10 * DAY, 12 * DAY] {
+            assert!(matches!(resolve_slot(&event, Some(start)), Ok(slot) if slot == (1, start)));
+        }
+        // An exception, a time between occurrences, a day past the count, and no occurrence
+        for occurrence in [Some(11 * DAY), Some(10 * DAY + 1), Some(13 * DAY), None] {
+            let Err(Error::InvalidInput { fields, .. }) = resolve_slot(&event, occurrence) else {
+                panic!("{:?} should not resolve", occurrence);
+            };
+            assert_eq!(fields[0].field, "occurrence");
+        }
+    }
+
+    #[test]
+    fn resolves_one_off_events_to_the_whole_event() {
+        let event = Event {
+            starts_at: Some(10 * DAY),
+            ..crate::test_event(1)
+        };
+        assert!(matches!(resolve_slot(&event, None), Ok((1, WHOLE_EVENT))));
+        assert!(matches!(resolve_slot(&event, Some(10 * DAY)), Ok((1, WHOLE_EVENT))));
+        assert!(resolve_slot(&event, Some(11 * DAY)).is_err());
+        assert!(matches!(resolve_slot(&crate::test_event(2), None), Ok((2, WHOLE_EVENT))));
+    }
+
+    #[test]
+    fn backfills_answers_for_seats_and_waitlist_entries() {
+        let slot = (1, WHOLE_EVENT);
+        let occurrence = (1, 10 * DAY);
+        // Seats and waitlist entries written before RSVP records existed
+        ATTENDEES.with(|a| a.borrow_mut().insert((slot, StorablePrincipal(principal(1))), 5));
+        ATTENDEES.with(|a| {
+            a.borrow_mut().insert((occurrence, StorablePrincipal(principal(2))), 6)
+        });
+        WAITLIST.with(|w| w.borrow_mut().insert((slot, 0), (StorablePrincipal(principal(3)), 7)));
+        set_rsvp(slot, principal(4), RsvpStatus::Maybe, 8);
+        // Another event is left alone
+        let other = (2, WHOLE_EVENT);
+        ATTENDEES.with(|a| a.borrow_mut().insert((other, StorablePrincipal(principal(1))), 9));
+
+        backfill_rsvps(1);
+        let answer = |slot, n| rsvp(slot, principal(n)).map(|r| (r.status, r.responded_at));
+        assert!(answer(slot, 1) == Some((RsvpStatus::Going, 5)));
+        assert!(answer(occurrence, 2) == Some((RsvpStatus::Going, 6)));
+        assert!(answer(slot, 3) == Some((RsvpStatus::Waitlisted, 7)));
+        assert!(answer(slot, 4) == Some((RsvpStatus::Maybe, 8)));
+        assert!(answer(other, 1).is_none());
+    }
+}
@@ -0,0 +1,186 @@
+// Append-only audit log of event mutations.
+//
+// Every mutation appends an entry to `AUDIT_LOG`, a stable `Log` whose entry
+// index doubles as the entry's sequence number. `AUDIT_BY_EVENT` and
+// `AUDIT_BY_PRINCIPAL` map `(event id, seq)` and `(caller, seq)` to nothing so
+// both can be paged through without scanning the log.
+//
+// Entries carry a field-level diff between the event before and after the
+// mutation. Values are rendered as JSON, so principals show in their textual
+// form; bookkeeping fields (`updated_at`, `version`) are left out.
+
+use candid::{CandidType, Decode, Encode, Principal};
+use ic_cdk::api::time;
+use ic_cdk::caller;
+use ic_stable_structures::Storable;
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::ops::Bound;
+
+use crate::{Event, StorablePrincipal, AUDIT_BY_EVENT, AUDIT_BY_PRINCIPAL, AUDIT_LOG};
+
+// Largest page size served by the audit log queries
+const MAX_AUDIT_PAGE: usize = 100;
+
+// Event fields that change on every write and would only add noise to a diff
+const UNAUDITED_FIELDS: [&str; 2] = ["updated_at", "version"];
+
+#[derive(CandidType, Clone, Serialize, Deserialize)]
+pub enum AuditOperation {
+    Create,
+    Update,
+    Attend {
+        occurrence: Option<u64>,
+        // Set when the caller joined the waitlist instead of taking a seat
+        waitlist_position: Option<u64>,
+    },
+    CancelAttendance { occurrence: Option<u64> },
+    DeclineAttendee { occurrence: Option<u64>, principal: Principal },
+    ProposeOwnershipTransfer { to: Principal },
+    CancelOwnershipTransfer,
+    // The proposed owner accepted the transfer
+    TransferOwnership,
+    Delete,
+    Restore,
+    ForceDelete,
+    // The event's editable fields were set back to those of a stored revision
+    RollBack { revision: u64 },
+}
+
+// A field whose value changed; `old` is unset for new fields, `new` for removed ones
+#[derive(CandidType, Clone, Serialize, Deserialize)]
+pub struct FieldChange {
+    pub field: String,
+    pub old: Option<String>,
+    pub new: Option<String>,
+}
+
+#[derive(CandidType, Clone, Serialize, Deserialize)]
+pub struct AuditEntry {
+    pub seq: u64,
+    pub event_id: u64,
+    pub caller: Principal,
+    pub at: u64,
+    pub operation: AuditOperation,
+    pub changes: Vec<FieldChange>,
+}
+
+// Page of audit entries
+#[derive(CandidType, Serialize, Deserialize)]
+pub struct AuditPage {
+    pub entries: Vec<AuditEntry>,
+    // Sequence number to continue after
+    pub next_cursor: Option<u64>,
+}
+
+impl Storable for AuditEntry {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+// Top-level fields of an event rendered as JSON
+fn fields(event: Option<&Event>) -> serde_json::Map<String, serde_json::Value> {
+    match event.map(serde_json::to_value) {
+        Some(Ok(serde_json::Value::Object(fields))) => fields,
+        _ => serde_json::Map::new(),
+    }
+}
+
+// Fields that differ between two states of an event
+pub fn diff(old: Option<&Event>, new: Option<&Event>) -> Vec<FieldChange> {
+    let (old, new) = (fields(old), fields(new));
+    let mut names: Vec<&String> = old.keys().chain(new.keys()).collect();
+    names.sort();
+    names.dedup();
+    names
+        .into_iter()
+        .filter(|name| !UNAUDITED_FIELDS.contains(&name.as_str()))
+        .filter_map(|name| {
+            let (old, new) = (old.get(name), new.get(name));
+            (old != new).then(|| FieldChange {
+                field: name.clone(),
+                old: old.map(|value| value.to_string()),
+                new: new.map(|value| value.to_string()),
+            })
+        })
+        .collect()
+}
+
+// Appends an entry for a mutation of event `event_id` by the caller
+pub fn record(event_id: u64, operation: AuditOperation, old: Option<&Event>, new: Option<&Event>) {
+    let caller = caller();
+    let seq = AUDIT_LOG.with(|log| log.borrow().len());
+    let entry = AuditEntry {
+        seq,
+        event_id,
+        caller,
+        at: time(),
+        operation,
+        changes: diff(old, new),
+    };
+    AUDIT_LOG
+        .with(|log| log.borrow().append(&entry))
+        .expect("cannot append to the audit log");
+    AUDIT_BY_EVENT.with(|a| a.borrow_mut().insert((event_id, seq), ()));
+    AUDIT_BY_PRINCIPAL.with(|a| a.borrow_mut().insert((StorablePrincipal(caller), seq), ()));
+}
+
+// Reads the entries with the given sequence numbers into a page
+fn page_of(mut seqs: Vec<u64>, limit: usize) -> AuditPage {
+    // An extra entry means there is at least one more page
+    let next_cursor = if seqs.len() > limit {
+        seqs.truncate(limit);
+        seqs.last().copied()
+    } else {
+        None
+    };
+    let entries = AUDIT_LOG.with(|log| {
+        let log = log.borrow();
+        seqs.into_iter().filter_map(|seq| log.get(seq)).collect()
+    });
+    AuditPage {
+        entries,
+        next_cursor,
+    }
+}
+
+// Lists the entries of event `id` oldest first, starting after sequence number `cursor`
+pub fn for_event(id: u64, cursor: Option<u64>, limit: u32) -> AuditPage {
+    let start = match cursor {
+        Some(seq) => Bound::Excluded((id, seq)),
+        None => Bound::Included((id, 0)),
+    };
+    let limit = (limit as usize).clamp(1, MAX_AUDIT_PAGE);
+    let seqs = AUDIT_BY_EVENT.with(|a| {
+        a.borrow()
+            .range((start, Bound::Included((id, u64::MAX))))
+            .take(limit + 1)
+            .map(|((_, seq), _)| seq)
+            .collect()
+    });
+    page_of(seqs, limit)
+}
+
+// Lists the entries recorded for calls by `principal` oldest first, starting after
+// sequence number `cursor`
+pub fn for_principal(principal: Principal, cursor: Option<u64>, limit: u32) -> AuditPage {
+    let key = StorablePrincipal(principal);
+    let start = match cursor {
+        Some(seq) => Bound::Excluded((key, seq)),
+        None => Bound::Included((key, 0)),
+    };
+    let limit = (limit as usize).clamp(1, MAX_AUDIT_PAGE);
+    let seqs = AUDIT_BY_PRINCIPAL.with(|a| {
+        a.borrow()
+            .range((start, Bound::Included((key, u64::MAX))))
+            .take(limit + 1)
+            .map(|((_, seq), _)| seq)
+            .collect()
+    });
+    page_of(seqs, limit)
+}
@@ -0,0 +1,673 @@
+// Bulk import and export of events for canister admins.
+//
+// Imports take a JSON array of objects or a CSV document with a header row.
+// Both name their fields like `EventPayload`, plus an optional `owner`
+// (textual principal, the importing admin when unset); in CSV, `recurrence` is
+// a JSON-encoded `RecurrenceRule`. Unknown fields are ignored, so an export can
+// be imported again. Every row goes through the same validation as
+// create_event, except that past start times are accepted and rows without
+// any schedule field create unscheduled events, like those stored before
+// schedules existed. Rows cannot name a banned owner.
+//
+// Imports are all or nothing: a dry run, or a run where any row fails, creates
+// no events and reports every failing row. Rows are numbered from 1, not
+// counting the CSV header.
+//
+// Exports page through `STORAGE` by id, hidden events included, with all the
+// fields of each event; in CSV the `recurrence` column holds JSON. The seats of
+// an event, with the occurrence they are for and when they were taken, are
+// paged separately by export_event_seats, since an event can have any number.
+
+use candid::{CandidType, Principal};
+use serde::{Deserialize, Serialize};
+use std::ops::Bound;
+
+use crate::attendance::{self, WHOLE_EVENT};
+use crate::error::FieldError;
+use crate::recurrence::RecurrenceRule;
+use crate::{
+    admin, check_size, insert_new_event, new_event, validate_capacity, validate_schedule,
+    validation, Error, Event, EventPayload, STORAGE,
+};
+
+// Most rows accepted by a single import
+const MAX_IMPORT_ROWS: usize = 500;
+
+// Largest page size served by export_events
+const MAX_EXPORT_PAGE: usize = 100;
+
+// Largest page size served by export_event_seats
+const MAX_EXPORT_SEATS_PAGE: usize = 1_000;
+
+// Columns of a CSV event export, in order
+const CSV_COLUMNS: [&str; 16] = [
+    "id",
+    "owner",
+    "event_title",
+    "event_description",
+    "event_location",
+    "event_card_imgurl",
+    "starts_at",
+    "ends_at",
+    "time_zone",
+    "capacity",
+    "recurrence",
+    "attendee_count",
+    "created_at",
+    "updated_at",
+    "version",
+    "hidden_at",
+];
+
+// Columns of a CSV seat export, in order
+const CSV_SEAT_COLUMNS: [&str; 4] = ["event_id", "principal", "occurrence", "joined_at"];
+
+#[derive(CandidType, Clone, Copy, Serialize, Deserialize)]
+pub enum BulkFormat {
+    Json,
+    Csv,
+}
+
+// Validation failure of one imported row
+#[derive(CandidType, Serialize, Deserialize)]
+pub struct RowError {
+    pub row: u32,
+    pub msg: String,
+    pub fields: Vec<FieldError>,
+}
+
+// Outcome of import_events
+#[derive(CandidType, Serialize, Deserialize)]
+pub struct ImportReport {
+    pub dry_run: bool,
+    pub rows: u32,
+    // Ids of the created events, in row order; empty on dry runs and failed imports
+    pub created: Vec<u64>,
+    pub errors: Vec<RowError>,
+}
+
+// Page of export_events
+#[derive(CandidType, Serialize, Deserialize)]
+pub struct ExportPage {
+    pub data: String,
+    // Event id to continue after
+    pub next_cursor: Option<u64>,
+}
+
+// Position to resume a seat export from: the last seat returned
+#[derive(CandidType, Clone, Copy, Serialize, Deserialize)]
+pub struct SeatCursor {
+    pub occurrence: Option<u64>,
+    pub principal: Principal,
+}
+
+// Page of export_event_seats
+#[derive(CandidType, Serialize, Deserialize)]
+pub struct SeatExportPage {
+    pub data: String,
+    pub next_cursor: Option<SeatCursor>,
+}
+
+// A row of an import, before validation
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct ImportRow {
+    owner: Option<String>,
+    event_title: String,
+    event_description: String,
+    event_location: String,
+    event_card_imgurl: String,
+    starts_at: Option<u64>,
+    ends_at: Option<u64>,
+    time_zone: Option<String>,
+    recurrence: Option<RecurrenceRule>,
+    capacity: Option<u32>,
+}
+
+impl ImportRow {
+    // Whether the row sets any field of the schedule
+    fn has_schedule(&self) -> bool {
+        self.starts_at.is_some()
+            || self.ends_at.is_some()
+            || self.time_zone.is_some()
+            || self.recurrence.is_some()
+    }
+}
+
+// A seat of an exported event
+#[derive(Serialize)]
+struct ExportedSeat {
+    event_id: u64,
+    principal: Principal,
+    // Start of the occurrence; unset for one-off events
+    occurrence: Option<u64>,
+    joined_at: u64,
+}
+
+// Validates every row of `data` and, unless `dry_run` is set or a row failed,
+// creates the events, owned by `importer` where the row names no owner
+pub fn import(
+    format: BulkFormat,
+    data: &str,
+    dry_run: bool,
+    importer: Principal,
+) -> Result<ImportReport, Error> {
+    let rows = match format {
+        BulkFormat::Json => json_rows(data)?,
+        BulkFormat::Csv => csv_rows(data)?,
+    };
+    if rows.len() > MAX_IMPORT_ROWS {
+        return Err(Error::invalid_field(
+            "data",
+            format!(
+                "holds {} rows; at most {} can be imported at once",
+                rows.len(),
+                MAX_IMPORT_ROWS
+            ),
+        ));
+    }
+
+    let mut events = Vec::new();
+    let mut errors = Vec::new();
+    for (row, parsed) in (1..).zip(rows) {
+        match parsed.and_then(|row| build_event(row, importer)) {
+            Ok(event) => events.push(event),
+            Err(error) => errors.push(row_error(row, error)),
+        }
+    }
+
+    let mut report = ImportReport {
+        dry_run,
+        rows: (events.len() + errors.len()) as u32,
+        created: Vec::new(),
+        errors,
+    };
+    if !dry_run && report.errors.is_empty() {
+        for event in events {
+            report.created.push(insert_new_event(event)?.id);
+        }
+    }
+    Ok(report)
+}
+
+// Validates a row the way create_event validates a payload and builds its event
+fn build_event(row: ImportRow, importer: Principal) -> Result<Event, Error> {
+    let scheduled = row.has_schedule();
+    let owner = match row.owner.filter(|owner| !owner.is_empty()) {
+        Some(text) => match Principal::from_text(&text) {
+            Ok(owner) if owner != Principal::anonymous() => owner,
+            _ => {
+                return Err(Error::invalid_field(
+                    "owner",
+                    format!("{} is not a principal that can own events", text),
+                ))
+            }
+        },
+        None => importer,
+    };
+    if admin::is_banned(owner) {
+        return Err(Error::invalid_field(
+            "owner",
+            format!("{} is banned from creating events", owner),
+        ));
+    }
+    let mut payload = EventPayload {
+        event_description: row.event_description,
+        event_title: row.event_title,
+        event_location: row.event_location,
+        event_card_imgurl: row.event_card_imgurl,
+        starts_at: row.starts_at.unwrap_or_default(),
+        ends_at: row.ends_at.unwrap_or_default(),
+        time_zone: row.time_zone.unwrap_or_default(),
+        recurrence: row.recurrence,
+        capacity: row.capacity,
+    };
+    validation::validate(&mut payload)?;
+    // Catalogs being migrated may hold past events
+    if scheduled {
+        validate_schedule(&payload, false)?;
+    } else {
+        validate_capacity(payload.capacity)?;
+    }
+    let mut event = new_event(payload, owner);
+    if !scheduled {
+        event.starts_at = None;
+        event.ends_at = None;
+        event.time_zone = None;
+    }
+    check_size(&event)?;
+    Ok(event)
+}
+
+fn row_error(row: u32, error: Error) -> RowError {
+    let (_, _, msg) = error.parts();
+    let msg = msg.to_string();
+    let fields = match error {
+        Error::InvalidInput { fields, .. } => fields,
+        _ => Vec::new(),
+    };
+    RowError { row, msg, fields }
+}
+
+// Rows of a JSON array; an element that is not a valid row fails on its own
+fn json_rows(data: &str) -> Result<Vec<Result<ImportRow, Error>>, Error> {
+    let values: Vec<serde_json::Value> = serde_json::from_str(data)
+        .map_err(|e| Error::invalid_field("data", format!("is not a JSON array: {}", e)))?;
+    Ok(values
+        .into_iter()
+        .map(|value| {
+            serde_json::from_value(value).map_err(|e| Error::InvalidInput {
+                msg: format!("invalid row: {}", e),
+                fields: Vec::new(),
+            })
+        })
+        .collect())
+}
+
+// Rows of a CSV document; the header names the column of every field
+fn csv_rows(data: &str) -> Result<Vec<Result<ImportRow, Error>>, Error> {
+    let mut records = parse_csv(data)
+        .map_err(|msg| Error::invalid_field("data", msg))?
+        .into_iter();
+    let header = records
+        .next()
+        .ok_or_else(|| Error::invalid_field("data", "has no header row"))?;
+    Ok(records.map(|record| csv_row(&header, record)).collect())
+}
+
+fn csv_row(header: &[String], record: Vec<String>) -> Result<ImportRow, Error> {
+    let mut row = ImportRow::default();
+    let mut fields = Vec::new();
+    for (column, value) in header.iter().zip(record) {
+        let column = column.trim();
+        let mut fail = |msg: String| {
+            fields.push(FieldError {
+                field: column.to_string(),
+                msg,
+            })
+        };
+        match column {
+            "owner" => row.owner = Some(value),
+            "event_title" => row.event_title = value,
+            "event_description" => row.event_description = value,
+            "event_location" => row.event_location = value,
+            "event_card_imgurl" => row.event_card_imgurl = value,
+            "time_zone" => row.time_zone = Some(value).filter(|value| !value.is_empty()),
+            "starts_at" => match parse_cell(&value) {
+                Ok(starts_at) => row.starts_at = starts_at,
+                Err(msg) => fail(msg),
+            },
+            "ends_at" => match parse_cell(&value) {
+                Ok(ends_at) => row.ends_at = ends_at,
+                Err(msg) => fail(msg),
+            },
+            "capacity" => match parse_cell(&value) {
+                Ok(capacity) => row.capacity = capacity,
+                Err(msg) => fail(msg),
+            },
+            "recurrence" if !value.is_empty() => match serde_json::from_str(&value) {
+                Ok(rule) => row.recurrence = Some(rule),
+                Err(e) => fail(e.to_string()),
+            },
+            _ => {}
+        }
+    }
+    if fields.is_empty() {
+        Ok(row)
+    } else {
+        let names: Vec<&str> = fields.iter().map(|f| f.field.as_str()).collect();
+        Err(Error::InvalidInput {
+            msg: format!("invalid {}", names.join(", ")),
+            fields,
+        })
+    }
+}
+
+// Parses a number cell; empty cells are unset
+fn parse_cell<T: std::str::FromStr>(value: &str) -> Result<Option<T>, String> {
+    let value = value.trim();
+    if value.is_empty() {
+        return Ok(None);
+    }
+    value
+        .parse()
+        .map(Some)
+        .map_err(|_| format!("{:?} is not a number", value))
+}
+
+// Splits a CSV document (RFC 4180, LF or CRLF line breaks) into records,
+// skipping blank lines and a leading byte order mark
+fn parse_csv(data: &str) -> Result<Vec<Vec<String>>, String> {
+    let data = data.strip_prefix('\u{feff}').unwrap_or(data);
+    let mut records = Vec::new();
+    let mut record = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = data.chars().peekable();
+    while let Some(c) = chars.next() {
+        match (quoted, c) {
+            (true, '"') if chars.peek() == Some(&'"') => {
+                chars.next();
+                field.push('"');
+            }
+            (true, '"') => quoted = false,
+            (true, _) => field.push(c),
+            (false, '"') if field.is_empty() => quoted = true,
+            (false, ',') => record.push(std::mem::take(&mut field)),
+            (false, '\r') if chars.peek() == Some(&'\n') => {}
+            (false, '\n') => {
+                record.push(std::mem::take(&mut field));
+                if record.len() > 1 || !record[0].is_empty() {
+                    records.push(std::mem::take(&mut record));
+                }
+                record.clear();
+            }
+            (false, _) => field.push(c),
+        }
+    }
+    if quoted {
+        return Err("ends inside a quoted field".to_string());
+    }
+    if !field.is_empty() || !record.is_empty() {
+        record.push(field);
+        records.push(record);
+    }
+    Ok(records)
+}
+
+// Lists events by id starting after `cursor` in `format`
+pub fn export(format: BulkFormat, cursor: Option<u64>, limit: u32) -> Result<ExportPage, Error> {
+    let start = match cursor {
+        Some(id) => Bound::Excluded(id),
+        None => Bound::Unbounded,
+    };
+    let limit = (limit as usize).clamp(1, MAX_EXPORT_PAGE);
+    let mut events: Vec<Event> = STORAGE.with(|s| {
+        s.borrow()
+            .range((start, Bound::Unbounded))
+            .take(limit + 1)
+            .map(|(_, event)| event)
+            .collect()
+    });
+
+    // An extra entry means there is at least one more page
+    let next_cursor = if events.len() > limit {
+        events.truncate(limit);
+        events.last().map(|event| event.id)
+    } else {
+        None
+    };
+    let data = match format {
+        BulkFormat::Json => to_json(&events)?,
+        BulkFormat::Csv => to_csv(&events)?,
+    };
+    Ok(ExportPage { data, next_cursor })
+}
+
+// Lists the seats of event `id` starting after `cursor` in `format`
+pub fn export_seats(
+    format: BulkFormat,
+    id: u64,
+    cursor: Option<SeatCursor>,
+    limit: u32,
+) -> Result<SeatExportPage, Error> {
+    if !STORAGE.with(|s| s.borrow().contains_key(&id)) {
+        return Err(Error::event_not_found(id));
+    }
+    let after = cursor.map(|cursor| (cursor.occurrence.unwrap_or(WHOLE_EVENT), cursor.principal));
+    let limit = (limit as usize).clamp(1, MAX_EXPORT_SEATS_PAGE);
+    let mut seats: Vec<ExportedSeat> = attendance::seats_of_event(id, after, limit + 1)
+        .into_iter()
+        .map(|((_, occurrence), principal, joined_at)| ExportedSeat {
+            event_id: id,
+            principal,
+            occurrence: (occurrence != WHOLE_EVENT).then_some(occurrence),
+            joined_at,
+        })
+        .collect();
+
+    // An extra entry means there is at least one more page
+    let next_cursor = if seats.len() > limit {
+        seats.truncate(limit);
+        seats.last().map(|seat| SeatCursor {
+            occurrence: seat.occurrence,
+            principal: seat.principal,
+        })
+    } else {
+        None
+    };
+    let data = match format {
+        BulkFormat::Json => to_json(&seats)?,
+        BulkFormat::Csv => seats_to_csv(&seats),
+    };
+    Ok(SeatExportPage { data, next_cursor })
+}
+
+fn to_json(value: &impl Serialize) -> Result<String, Error> {
+    serde_json::to_string(value).map_err(|e| Error::Internal {
+        msg: format!("cannot encode the export: {}", e),
+    })
+}
+
+fn seats_to_csv(seats: &[ExportedSeat]) -> String {
+    let mut out = String::new();
+    push_csv_record(&mut out, CSV_SEAT_COLUMNS.iter().map(|column| column.to_string()));
+    for seat in seats {
+        push_csv_record(
+            &mut out,
+            [
+                seat.event_id.to_string(),
+                seat.principal.to_text(),
+                seat.occurrence.map(|value| value.to_string()).unwrap_or_default(),
+                seat.joined_at.to_string(),
+            ]
+            .into_iter(),
+        );
+    }
+    out
+}
+
+fn to_csv(events: &[Event]) -> Result<String, Error> {
+    let mut out = String::new();
+    push_csv_record(&mut out, CSV_COLUMNS.iter().map(|column| column.to_string()));
+    let number = |value: Option<u64>| value.map(|value| value.to_string()).unwrap_or_default();
+    for event in events {
+        let recurrence = match &event.recurrence {
+            Some(rule) => to_json(rule)?,
+            None => String::new(),
+        };
+        push_csv_record(
+            &mut out,
+            [
+                event.id.to_string(),
+                event.owner.to_text(),
+                event.event_title.clone(),
+                event.event_description.clone(),
+                event.event_location.clone(),
+                event.event_card_imgurl.clone(),
+                number(event.starts_at),
+                number(event.ends_at),
+                event.time_zone.clone().unwrap_or_default(),
+                number(event.capacity.map(u64::from)),
+                recurrence,
+                event.attendee_count.to_string(),
+                event.created_at.to_string(),
+                number(event.updated_at),
+                event.version.to_string(),
+                number(event.hidden_at),
+            ]
+            .into_iter(),
+        );
+    }
+    Ok(out)
+}
+
+// Appends a CSV record, quoting fields that need it
+fn push_csv_record(out: &mut String, fields: impl Iterator<Item = String>) {
+    for (i, field) in fields.enumerate() {
+        if i > 0 {
+            out.push(',');
+        }
+        if field.contains([',', '"', '\n', '\r']) {
+            out.push('"');
+            out.push_str(&field.replace('"', "\"\""));
+            out.push('"');
+        } else {
+            out.push_str(&field);
+        }
+    }
+    out.push_str("\r\n");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(fields: &[&str]) -> Vec<String> {
+        fields.iter().map(|field| field.to_string()).collect()
+    }
+
+    fn header() -> Vec<String> {
+        strings(&["event_title", "starts_at", "ends_at", "capacity", "recurrence", "unknown"])
+    }
+
+    #[test]
+    fn parses_plain_records_with_either_line_break() {
+        assert_eq!(
+            parse_csv("a,b,c\nd,e,f\r\ng,,i").unwrap(),
+            vec![strings(&["a", "b", "c"]), strings(&["d", "e", "f"]), strings(&["g", "", "i"])]
+        );
+    }
+
+    #[test]
+    fn parses_quoted_fields() {
+        let data = "\"a,b\",\"say \"\"hi\"\"\",\"line\r\nbreak\"\r\n\"\",x\r\n";
+        assert_eq!(
+            parse_csv(data).unwrap(),
+            vec![strings(&["a,b", "say \"hi\"", "line\r\nbreak"]), strings(&["", "x"])]
+        );
+    }
+
+    #[test]
+    fn skips_blank_lines_and_a_byte_order_mark() {
+        assert_eq!(
+            parse_csv("\u{feff}a,b\n\n\r\nc,d\n").unwrap(),
+            vec![strings(&["a", "b"]), strings(&["c", "d"])]
+        );
+        assert!(parse_csv("").unwrap().is_empty());
+    }
+
+    #[test]
+    fn keeps_trailing_empty_fields() {
+        assert_eq!(parse_csv("a,\n,\n").unwrap(), vec![strings(&["a", ""]), strings(&["", ""])]);
+    }
+
+    #[test]
+    fn quotes_inside_unquoted_fields_are_literal() {
+        assert_eq!(parse_csv("a\"b,c\n").unwrap(), vec![strings(&["a\"b", "c"])]);
+    }
+
+    #[test]
+    fn rejects_an_unterminated_quote() {
+        assert!(parse_csv("a,\"b\nc").is_err());
+    }
+
+    #[test]
+    fn written_records_parse_back() {
+        let records = vec![
+            strings(&["plain", "with,comma", "with \"quotes\"", "multi\nline", ""]),
+            strings(&["crlf\r\n", "\"", ",", "ünïcödé", "x"]),
+        ];
+        let mut out = String::new();
+        for record in &records {
+            push_csv_record(&mut out, record.iter().cloned());
+        }
+        assert_eq!(parse_csv(&out).unwrap(), records);
+    }
+
+    #[test]
+    fn reads_row_fields_by_header() {
+        let rule = r#"{"frequency":"Daily","by_day":[],"count":3,"exceptions":[]}"#;
+        let record = strings(&["Launch", " 10 ", "20", "", rule, "x"]);
+        let Ok(row) = csv_row(&header(), record) else {
+            panic!("row should parse");
+        };
+        assert_eq!(row.event_title, "Launch");
+        assert_eq!(row.starts_at, Some(10));
+        assert_eq!(row.ends_at, Some(20));
+        assert_eq!(row.capacity, None);
+        assert_eq!(row.recurrence.and_then(|rule| rule.count), Some(3));
+    }
+
+    #[test]
+    fn reports_every_invalid_cell() {
+        let record = strings(&["Launch", "soon", "20", "-1", "{", ""]);
+        let Err(Error::InvalidInput { msg, fields }) = csv_row(&header(), record) else {
+            panic!("row should fail");
+        };
+        assert_eq!(msg, "invalid starts_at, capacity, recurrence");
+        let names: Vec<&str> = fields.iter().map(|field| field.field.as_str()).collect();
+        assert_eq!(names, ["starts_at", "capacity", "recurrence"]);
+    }
+
+    type Rows = Result<Vec<Result<ImportRow, Error>>, Error>;
+
+    // Parses the single row of an exported document
+    fn only_row(export: Result<String, Error>, rows: fn(&str) -> Rows) -> ImportRow {
+        let Ok(data) = export else {
+            panic!("export should succeed");
+        };
+        let Ok(mut rows) = rows(&data) else {
+            panic!("export should parse");
+        };
+        let (Some(Ok(row)), true) = (rows.pop(), rows.is_empty()) else {
+            panic!("export should hold one valid row");
+        };
+        row
+    }
+
+    #[test]
+    fn exported_unscheduled_events_import_without_a_schedule() {
+        let event = Event {
+            event_title: "Launch".to_string(),
+            ..crate::test_event(7)
+        };
+        let row = only_row(to_csv(std::slice::from_ref(&event)), csv_rows);
+        assert!(!row.has_schedule());
+        assert_eq!(row.event_title, "Launch");
+        assert!(!only_row(to_json(&[event]), json_rows).has_schedule());
+    }
+
+    #[test]
+    fn exported_scheduled_events_keep_their_schedule() {
+        let event = Event {
+            starts_at: Some(10),
+            ends_at: Some(20),
+            time_zone: Some("UTC".to_string()),
+            ..crate::test_event(8)
+        };
+        let row = only_row(to_csv(&[event]), csv_rows);
+        assert!(row.has_schedule());
+        assert_eq!(row.starts_at, Some(10));
+        assert_eq!(row.time_zone.as_deref(), Some("UTC"));
+    }
+
+    #[test]
+    fn csv_import_needs_a_header() {
+        assert!(csv_rows("").is_err());
+        let Ok(rows) = csv_rows("event_title\nA\nB\n") else {
+            panic!("document should parse");
+        };
+        assert_eq!(rows.len(), 2);
+    }
+
+    #[test]
+    fn json_rows_fail_one_by_one() {
+        let Ok(rows) = json_rows(r#"[{"event_title": "A"}, {"event_title": 5}, {}]"#) else {
+            panic!("array should parse");
+        };
+        let parsed: Vec<bool> = rows.iter().map(Result::is_ok).collect();
+        assert_eq!(parsed, [true, false, true]);
+        assert!(json_rows("{}").is_err());
+    }
+}
@@ -0,0 +1,401 @@
+// Certification of stored events.
+//
+// Every event in `STORAGE` is a leaf of a Merkle tree following the IC hash
+// tree format, and the root hash is the canister's certified data:
+//
+//     labeled("events", trie)
+//
+// The trie is a binary tree of fixed depth 64 over the bits of the event id,
+// most significant first. Each id ends in `labeled(id, leaf(sha256(candid)))`,
+// the id as 8 big-endian bytes and the leaf the SHA-256 of the event's candid
+// encoding. Subtrees without events are `empty`, so a write only rehashes the
+// 64 nodes on its id's path. The hashes of non-empty nodes are kept in
+// `CERT_TREE` under `(depth, id prefix)`, which survives upgrades; certified
+// data does not, and is set again from `post_upgrade`.
+//
+// A client verifies a get_event_certified response by checking the certificate
+// with the IC root key, that its `certified_data` equals the reconstructed root
+// hash of the witness, and that the witness leaf for the id equals the SHA-256 of
+// `encoded_event`.
+
+use candid::{CandidType, Encode};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::{Event, CERT_TREE};
+
+type Hash = [u8; 32];
+
+// Depth of the id trie: one level per bit of a u64
+const ID_BITS: u8 = 64;
+
+// Label of the events subtree
+const EVENTS_LABEL: &[u8] = b"events";
+
+// CBOR self-describe tag written in front of every witness
+const CBOR_SELF_DESCRIBE: [u8; 3] = [0xd9, 0xd9, 0xf7];
+
+// Response of get_event_certified
+#[derive(CandidType, Serialize, Deserialize)]
+pub struct CertifiedEvent {
+    pub event: Event,
+    // Candid encoding of `event` whose SHA-256 is the certified leaf
+    pub encoded_event: Vec<u8>,
+    // System certificate over the canister's certified data
+    pub certificate: Vec<u8>,
+    // CBOR hash tree revealing the event's leaf and pruning everything else
+    pub witness: Vec<u8>,
+}
+
+// Hash tree revealed to clients; the variants follow the IC interface specification
+enum HashTree {
+    Empty,
+    Fork(Box<HashTree>, Box<HashTree>),
+    Labeled(Vec<u8>, Box<HashTree>),
+    Leaf(Vec<u8>),
+    Pruned(Hash),
+}
+
+fn domain_hash(domain: &str, parts: &[&[u8]]) -> Hash {
+    let mut hasher = Sha256::new();
+    hasher.update([domain.len() as u8]);
+    hasher.update(domain.as_bytes());
+    for part in parts {
+        hasher.update(part);
+    }
+    hasher.finalize().into()
+}
+
+fn empty_hash() -> Hash {
+    domain_hash("ic-hashtree-empty", &[])
+}
+
+fn fork_hash(left: &Hash, right: &Hash) -> Hash {
+    domain_hash("ic-hashtree-fork", &[left, right])
+}
+
+fn labeled_hash(label: &[u8], tree: &Hash) -> Hash {
+    domain_hash("ic-hashtree-labeled", &[label, tree])
+}
+
+fn leaf_hash(value: &[u8]) -> Hash {
+    domain_hash("ic-hashtree-leaf", &[value])
+}
+
+// Candid encoding of an event, as returned to clients and hashed into its leaf
+fn encode(event: &Event) -> Vec<u8> {
+    Encode!(event).unwrap()
+}
+
+fn event_digest(event: &Event) -> Hash {
+    Sha256::digest(encode(event)).into()
+}
+
+// Prefix of `id` naming its ancestor at `depth`
+fn prefix(id: u64, depth: u8) -> u64 {
+    id.checked_shr((ID_BITS - depth) as u32).unwrap_or(0)
+}
+
+fn node_hash(depth: u8, prefix: u64) -> Option<Hash> {
+    CERT_TREE.with(|t| t.borrow().get(&(depth, prefix)))
+}
+
+fn set_node(depth: u8, prefix: u64, hash: Option<Hash>) {
+    CERT_TREE.with(|t| match hash {
+        Some(hash) => t.borrow_mut().insert((depth, prefix), hash),
+        None => t.borrow_mut().remove(&(depth, prefix)),
+    });
+}
+
+// Updates the leaf of event `id` to `event`, or removes it, and rehashes its path
+pub fn update(id: u64, event: Option<&Event>) {
+    let leaf = event.map(|event| labeled_hash(&id.to_be_bytes(), &leaf_hash(&event_digest(event))));
+    set_node(ID_BITS, id, leaf);
+    for depth in (0..ID_BITS).rev() {
+        let child = prefix(id, depth) << 1;
+        let left = node_hash(depth + 1, child);
+        let right = node_hash(depth + 1, child | 1);
+        let hash = match (left, right) {
+            (None, None) => None,
+            (left, right) => Some(fork_hash(
+                &left.unwrap_or_else(empty_hash),
+                &right.unwrap_or_else(empty_hash),
+            )),
+        };
+        set_node(depth, prefix(id, depth), hash);
+    }
+}
+
+// Root hash of the whole tree
+fn root_hash() -> Hash {
+    let trie = node_hash(0, 0).unwrap_or_else(empty_hash);
+    labeled_hash(EVENTS_LABEL, &trie)
+}
+
+// Sets the canister's certified data to the current root hash
+pub fn publish() {
+    ic_cdk::api::set_certified_data(&root_hash());
+}
+
+// Witness revealing the leaf of `event` and pruning every other subtree
+fn witness(event: &Event) -> HashTree {
+    let id = event.id;
+    let mut tree = HashTree::Labeled(
+        id.to_be_bytes().to_vec(),
+        Box::new(HashTree::Leaf(event_digest(event).to_vec())),
+    );
+    for depth in (0..ID_BITS).rev() {
+        let sibling = prefix(id, depth + 1) ^ 1;
+        let sibling = match node_hash(depth + 1, sibling) {
+            Some(hash) => HashTree::Pruned(hash),
+            None => HashTree::Empty,
+        };
+        tree = if prefix(id, depth + 1) & 1 == 0 {
+            HashTree::Fork(Box::new(tree), Box::new(sibling))
+        } else {
+            HashTree::Fork(Box::new(sibling), Box::new(tree))
+        };
+    }
+    HashTree::Labeled(EVENTS_LABEL.to_vec(), Box::new(tree))
+}
+
+// CBOR encoding of a hash tree with the self-describe tag
+fn witness_cbor(event: &Event) -> Vec<u8> {
+    let mut out = CBOR_SELF_DESCRIBE.to_vec();
+    write_tree(&mut out, &witness(event));
+    out
+}
+
+fn write_head(out: &mut Vec<u8>, major: u8, len: usize) {
+    let major = major << 5;
+    match len {
+        0..=23 => out.push(major | len as u8),
+        24..=0xff => out.extend([major | 24, len as u8]),
+        _ => {
+            out.push(major | 25);
+            out.extend((len as u16).to_be_bytes());
+        }
+    }
+}
+
+fn write_bytes(out: &mut Vec<u8>, bytes: &[u8]) {
+    write_head(out, 2, bytes.len());
+    out.extend_from_slice(bytes);
+}
+
+fn write_tree(out: &mut Vec<u8>, tree: &HashTree) {
+    match tree {
+        HashTree::Empty => {
+            write_head(out, 4, 1);
+            write_head(out, 0, 0);
+        }
+        HashTree::Fork(left, right) => {
+            write_head(out, 4, 3);
+            write_head(out, 0, 1);
+            write_tree(out, left);
+            write_tree(out, right);
+        }
+        HashTree::Labeled(label, tree) => {
+            write_head(out, 4, 3);
+            write_head(out, 0, 2);
+            write_bytes(out, label);
+            write_tree(out, tree);
+        }
+        HashTree::Leaf(value) => {
+            write_head(out, 4, 2);
+            write_head(out, 0, 3);
+            write_bytes(out, value);
+        }
+        HashTree::Pruned(hash) => {
+            write_head(out, 4, 2);
+            write_head(out, 0, 4);
+            write_bytes(out, hash);
+        }
+    }
+}
+
+// Certified response for `event`; only available in non-replicated query calls
+pub fn certify(event: Event) -> Option<CertifiedEvent> {
+    let certificate = ic_cdk::api::data_certificate()?;
+    Some(CertifiedEvent {
+        encoded_event: encode(&event),
+        witness: witness_cbor(&event),
+        certificate,
+        event,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(id: u64, title: &str) -> Event {
+        Event {
+            event_title: title.to_string(),
+            ..crate::test_event(id)
+        }
+    }
+
+    // Reads a CBOR head, returning the major type and its argument
+    fn read_head(bytes: &mut &[u8]) -> (u8, usize) {
+        let (first, rest) = bytes.split_first().unwrap();
+        *bytes = rest;
+        let len = match first & 0x1f {
+            info @ 0..=23 => info as usize,
+            24 => {
+                let len = bytes[0] as usize;
+                *bytes = &bytes[1..];
+                len
+            }
+            25 => {
+                let len = u16::from_be_bytes([bytes[0], bytes[1]]) as usize;
+                *bytes = &bytes[2..];
+                len
+            }
+            info => panic!("unexpected additional information {}", info),
+        };
+        (first >> 5, len)
+    }
+
+    fn read_bytes(bytes: &mut &[u8]) -> Vec<u8> {
+        let (major, len) = read_head(bytes);
+        assert_eq!(major, 2);
+        let (value, rest) = bytes.split_at(len);
+        *bytes = rest;
+        value.to_vec()
+    }
+
+    // Decodes a hash tree written by `write_tree`
+    fn read_tree(bytes: &mut &[u8]) -> HashTree {
+        let (major, len) = read_head(bytes);
+        assert_eq!(major, 4);
+        let (major, tag) = read_head(bytes);
+        assert_eq!(major, 0);
+        match (tag, len) {
+            (0, 1) => HashTree::Empty,
+            (1, 3) => HashTree::Fork(Box::new(read_tree(bytes)), Box::new(read_tree(bytes))),
+            (2, 3) => HashTree::Labeled(read_bytes(bytes), Box::new(read_tree(bytes))),
+            (3, 2) => HashTree::Leaf(read_bytes(bytes)),
+            (4, 2) => HashTree::Pruned(read_bytes(bytes).try_into().unwrap()),
+            other => panic!("unexpected node {:?}", other),
+        }
+    }
+
+    fn decode_witness(witness: &[u8]) -> HashTree {
+        let mut bytes = witness.strip_prefix(&CBOR_SELF_DESCRIBE[..]).unwrap();
+        let tree = read_tree(&mut bytes);
+        assert!(bytes.is_empty());
+        tree
+    }
+
+    // Root hash a client reconstructs from a witness
+    fn reconstruct(tree: &HashTree) -> Hash {
+        match tree {
+            HashTree::Empty => empty_hash(),
+            HashTree::Fork(left, right) => fork_hash(&reconstruct(left), &reconstruct(right)),
+            HashTree::Labeled(label, tree) => labeled_hash(label, &reconstruct(tree)),
+            HashTree::Leaf(value) => leaf_hash(value),
+            HashTree::Pruned(hash) => *hash,
+        }
+    }
+
+    // Labeled leaves revealed by a witness
+    fn revealed(tree: &HashTree, leaves: &mut Vec<(Vec<u8>, Vec<u8>)>) {
+        match tree {
+            HashTree::Fork(left, right) => {
+                revealed(left, leaves);
+                revealed(right, leaves);
+            }
+            HashTree::Labeled(label, tree) => match tree.as_ref() {
+                HashTree::Leaf(value) => leaves.push((label.clone(), value.clone())),
+                tree => revealed(tree, leaves),
+            },
+            _ => {}
+        }
+    }
+
+    #[test]
+    fn hashes_are_domain_separated() {
+        let expected: Hash = Sha256::digest(b"\x11ic-hashtree-empty").into();
+        assert_eq!(empty_hash(), expected);
+        let mut hasher = Sha256::new();
+        hasher.update(b"\x10ic-hashtree-leaf");
+        hasher.update(b"value");
+        let expected: Hash = hasher.finalize().into();
+        assert_eq!(leaf_hash(b"value"), expected);
+    }
+
+    #[test]
+    fn empty_tree_is_a_labeled_empty_node() {
+        assert_eq!(root_hash(), labeled_hash(EVENTS_LABEL, &empty_hash()));
+    }
+
+    #[test]
+    fn cbor_encodes_every_node_kind() {
+        let mut out = Vec::new();
+        write_tree(&mut out, &HashTree::Leaf(b"ab".to_vec()));
+        assert_eq!(out, [0x82, 0x03, 0x42, b'a', b'b']);
+
+        let mut out = Vec::new();
+        write_tree(
+            &mut out,
+            &HashTree::Fork(Box::new(HashTree::Empty), Box::new(HashTree::Pruned([7; 32]))),
+        );
+        let mut expected = vec![0x83, 0x01, 0x81, 0x00, 0x82, 0x04, 0x58, 0x20];
+        expected.extend([7; 32]);
+        assert_eq!(out, expected);
+
+        let mut out = Vec::new();
+        write_bytes(&mut out, &[0; 300]);
+        assert_eq!(out[..3], [0x59, 0x01, 0x2c]);
+        assert_eq!(out.len(), 303);
+    }
+
+    #[test]
+    fn witnesses_rebuild_the_root_hash() {
+        let events: Vec<Event> = [0, 1, 2, 5, 1 << 40, u64::MAX]
+            .into_iter()
+            .map(|id| event(id, &format!("event {}", id)))
+            .collect();
+        for event in &events {
+            update(event.id, Some(event));
+        }
+
+        for event in &events {
+            let tree = decode_witness(&witness_cbor(event));
+            assert_eq!(reconstruct(&tree), root_hash());
+
+            let mut leaves = Vec::new();
+            revealed(&tree, &mut leaves);
+            let digest: Hash = Sha256::digest(encode(event)).into();
+            assert_eq!(leaves, vec![(event.id.to_be_bytes().to_vec(), digest.to_vec())]);
+        }
+    }
+
+    #[test]
+    fn stale_witnesses_no_longer_match() {
+        let old = event(3, "before");
+        update(3, Some(&old));
+        update(9, Some(&event(9, "other")));
+        let stale = decode_witness(&witness_cbor(&old));
+
+        let new = event(3, "after");
+        update(3, Some(&new));
+        assert_ne!(reconstruct(&stale), root_hash());
+        assert_eq!(reconstruct(&decode_witness(&witness_cbor(&new))), root_hash());
+    }
+
+    #[test]
+    fn removing_every_event_empties_the_tree() {
+        let empty_root = root_hash();
+        for id in [4, 6, 1 << 63] {
+            update(id, Some(&event(id, "event")));
+        }
+        assert_ne!(root_hash(), empty_root);
+        for id in [4, 6, 1 << 63] {
+            update(id, None);
+        }
+        assert_eq!(root_hash(), empty_root);
+        assert!(CERT_TREE.with(|t| t.borrow().is_empty()));
+    }
+}
@@ -0,0 +1,172 @@
+// Errors returned by the canister's endpoints.
+//
+// Clients should branch on the variant (or its numeric `code`), never on `msg`,
+// which is meant for humans and may change. Codes are stable: new variants get
+// new codes and existing codes are never reused.
+//
+//   1001  NotFound         the event (or entry) does not exist
+//   1002  NotAuthorized    the caller may not perform the operation
+//   1003  InvalidInput     the request failed validation; see `fields`
+//   1004  AlreadyExists    the entry the call would create is already there
+//   1005  CapacityReached  the event and its waitlist are full
+//   1006  EventClosed      the event (or occurrence) has already ended
+//   1007  (retired)
+//   1008  Conflict         the event changed concurrently
+//   1009  Internal         an unexpected failure inside the canister
+//   1010  AnonymousCaller  the endpoint needs an authenticated caller
+//
+// Calls stopped by an endpoint guard (see `guard::reject_anonymous`) never
+// reach the endpoint, so they come back as a rejection rather than an `Err`.
+// The reject message starts with the code and variant name, e.g.
+// "1010 AnonymousCaller: ...".
+
+use candid::{CandidType, Principal};
+use serde::{Deserialize, Serialize};
+
+// Validation failure of a single request field
+#[derive(CandidType, Clone, Serialize, Deserialize)]
+pub struct FieldError {
+    pub field: String,
+    pub msg: String,
+}
+
+#[derive(CandidType, Serialize, Deserialize)]
+pub enum Error {
+    // Indicates that the requested event was not found
+    NotFound { msg: String },
+
+    // Indicates an authorization error when the caller's role on the event does not permit the operation
+    NotAuthorized { msg: String, caller: Principal },
+
+    // Indicates that the request carried invalid data, with one entry per offending field
+    InvalidInput { msg: String, fields: Vec<FieldError> },
+
+    // Indicates that the caller already holds what the call would create, e.g. a seat
+    AlreadyExists { msg: String },
+
+    // Indicates that the event is full and its waitlist cannot take more principals
+    CapacityReached { msg: String, capacity: u32 },
+
+    // Indicates that the event (or the chosen occurrence) is over
+    EventClosed { msg: String },
+
+    // Indicates that the event was modified concurrently; `current_version` is its version now
+    Conflict { msg: String, current_version: u64 },
+
+    // Indicates an unexpected failure inside the canister
+    Internal { msg: String },
+
+    // Indicates that the endpoint refuses the anonymous principal
+    AnonymousCaller { msg: String },
+}
+
+// Stable numeric code of an error variant, as listed by get_error_codes
+#[derive(CandidType, Serialize, Deserialize)]
+pub struct ErrorCode {
+    pub code: u16,
+    pub name: String,
+}
+
+impl Error {
+    // Error for a missing event
+    pub fn event_not_found(id: u64) -> Self {
+        Self::NotFound {
+            msg: format!("Event with id={} not found", id),
+        }
+    }
+
+    // InvalidInput error blaming a single field
+    pub fn invalid_field(field: &str, msg: impl Into<String>) -> Self {
+        let msg = msg.into();
+        Self::InvalidInput {
+            msg: format!("invalid {}: {}", field, msg),
+            fields: vec![FieldError {
+                field: field.to_string(),
+                msg,
+            }],
+        }
+    }
+
+    // Stable code, variant name and message of the error
+    pub fn parts(&self) -> (u16, &'static str, &str) {
+        match self {
+            Self::NotFound { msg } => (1001, "NotFound", msg),
+            Self::NotAuthorized { msg, .. } => (1002, "NotAuthorized", msg),
+            Self::InvalidInput { msg, .. } => (1003, "InvalidInput", msg),
+            Self::AlreadyExists { msg } => (1004, "AlreadyExists", msg),
+            Self::CapacityReached { msg, .. } => (1005, "CapacityReached", msg),
+            Self::EventClosed { msg } => (1006, "EventClosed", msg),
+            Self::Conflict { msg, .. } => (1008, "Conflict", msg),
+            Self::Internal { msg } => (1009, "Internal", msg),
+            Self::AnonymousCaller { msg } => (1010, "AnonymousCaller", msg),
+        }
+    }
+
+    // Reject message carrying the error's code and variant name, for calls stopped by a guard
+    pub fn reject_message(&self) -> String {
+        let (code, name, msg) = self.parts();
+        format!("{} {}: {}", code, name, msg)
+    }
+
+    // HTTP status code the error is served with by http_request
+    pub fn http_status(&self) -> u16 {
+        match self {
+            Self::NotFound { .. } => 404,
+            Self::NotAuthorized { .. } => 403,
+            Self::InvalidInput { .. } => 400,
+            Self::AlreadyExists { .. } | Self::CapacityReached { .. } | Self::Conflict { .. } => 409,
+            Self::EventClosed { .. } => 410,
+            Self::Internal { .. } => 500,
+            Self::AnonymousCaller { .. } => 401,
+        }
+    }
+}
+
+// Every error code with its variant name, read from `parts` so the two cannot disagree
+pub fn codes() -> Vec<ErrorCode> {
+    let msg = String::new;
+    [
+        Error::NotFound { msg: msg() },
+        Error::NotAuthorized {
+            msg: msg(),
+            caller: Principal::anonymous(),
+        },
+        Error::InvalidInput {
+            msg: msg(),
+            fields: Vec::new(),
+        },
+        Error::AlreadyExists { msg: msg() },
+        Error::CapacityReached {
+            msg: msg(),
+            capacity: 0,
+        },
+        Error::EventClosed { msg: msg() },
+        Error::Conflict {
+            msg: msg(),
+            current_version: 0,
+        },
+        Error::Internal { msg: msg() },
+        Error::AnonymousCaller { msg: msg() },
+    ]
+    .iter()
+    .map(|error| {
+        let (code, name, _) = error.parts();
+        ErrorCode {
+            code,
+            name: name.to_string(),
+        }
+    })
+    .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn codes_stay_stable() {
+        // 1007 belonged to the retired RateLimited and is not reused
+        let codes: Vec<u16> = codes().iter().map(|code| code.code).collect();
+        assert_eq!(codes, [1001, 1002, 1003, 1004, 1005, 1006, 1008, 1009, 1010]);
+    }
+}
@@ -0,0 +1,295 @@
+// Authorization for mutating endpoints.
+//
+// Every update endpoint declares `guard = "reject_anonymous"`, so the shared
+// anonymous principal cannot create events or RSVP. An endpoint that should
+// serve anonymous callers opts out by dropping the guard from its attribute.
+//
+// Every update resolves the event it acts on through `authorize`, which loads
+// the event and checks that the caller may perform the requested action on it.
+// Failures come back as typed `NotFound`/`NotAuthorized` errors; nothing in this
+// path traps.
+//
+// Permission matrix (see `roles` for how roles are held):
+//
+//                     Owner  CoHost  Moderator  CheckInStaff  anyone
+//   Update              x      x
+//   Delete              x
+//   TransferOwnership   x
+//   RollBack            x
+//   ManageAttendees     x      x        x
+//   CheckIn             x      x        x            x
+//   ManageRoles         x      x
+//   ViewHistory         x      x        x
+//   Attend              x      x        x            x          x
+//
+// Managing roles is further limited to roles below the caller's own. Hidden
+// events are reported as missing to callers without a role on them, unless
+// they are canister admins or the principal a pending ownership transfer
+// names, who may still accept it.
+
+use candid::Principal;
+use ic_cdk::caller;
+
+use crate::roles::{self, Role};
+use crate::{admin, Error, Event, STORAGE};
+
+// What a caller wants to do with an event
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    Update,
+    Delete,
+    // Propose or withdraw handing the event over to another principal
+    TransferOwnership,
+    // Restore a previous revision of the event
+    RollBack,
+    // RSVP, cancel or leave the waitlist on the caller's own behalf
+    Attend,
+    // Change another principal's attendance, e.g. decline their RSVP
+    ManageAttendees,
+    // Mark attendees as arrived at the door
+    CheckIn,
+    // Grant or revoke the roles of other principals
+    ManageRoles,
+    // Read the audit log and the revisions of the event
+    ViewHistory,
+}
+
+impl Action {
+    fn describe(self) -> &'static str {
+        match self {
+            Action::Update => "update",
+            Action::Delete => "delete",
+            Action::TransferOwnership => "transfer the ownership of",
+            Action::RollBack => "roll back",
+            Action::Attend => "attend",
+            Action::ManageAttendees => "manage the attendees of",
+            Action::CheckIn => "check in attendees of",
+            Action::ManageRoles => "manage the roles of",
+            Action::ViewHistory => "read the history of",
+        }
+    }
+}
+
+// Endpoint guard rejecting calls from the anonymous principal
+pub fn reject_anonymous() -> Result<(), String> {
+    if caller() == Principal::anonymous() {
+        return Err(Error::AnonymousCaller {
+            msg: "This endpoint needs an authenticated caller".to_string(),
+        }
+        .reject_message());
+    }
+    Ok(())
+}
+
+// Whether holding `role` permits `action`
+fn permits(role: Role, action: Action) -> bool {
+    match action {
+        Action::Attend => true,
+        Action::Delete | Action::TransferOwnership | Action::RollBack => role == Role::Owner,
+        Action::Update | Action::ManageRoles => role <= Role::CoHost,
+        Action::ManageAttendees | Action::ViewHistory => role <= Role::Moderator,
+        Action::CheckIn => role <= Role::CheckInStaff,
+    }
+}
+
+// Whether `principal` may perform `action` on `event`
+fn is_allowed(event: &Event, principal: Principal, action: Action) -> bool {
+    match roles::role_of(event, principal) {
+        Some(role) => permits(role, action),
+        None => action == Action::Attend,
+    }
+}
+
+// Whether `principal` may see `event`
+pub fn is_visible(event: &Event, principal: Principal) -> bool {
+    event.hidden_at.is_none()
+        || roles::role_of(event, principal).is_some()
+        || admin::is_admin(principal)
+}
+
+// Whether `principal` is named by the pending ownership transfer of `event`
+fn is_transfer_recipient(event: &Event, principal: Principal) -> bool {
+    event.pending_transfer.as_ref().is_some_and(|transfer| transfer.to == principal)
+}
+
+// Loads the event with the given id for the caller to accept its ownership transfer,
+// which they may do even while the event is hidden
+pub fn authorize_transfer_recipient(id: u64) -> Result<Event, Error> {
+    let caller = caller();
+    STORAGE
+        .with(|s| s.borrow().get(&id))
+        .filter(|event| is_visible(event, caller) || is_transfer_recipient(event, caller))
+        .ok_or_else(|| Error::event_not_found(id))
+}
+
+// Loads the event with the given id and checks that the caller may perform `action` on it
+pub fn authorize(id: u64, action: Action) -> Result<Event, Error> {
+    let caller = caller();
+    let event = STORAGE
+        .with(|s| s.borrow().get(&id))
+        .filter(|event| is_visible(event, caller))
+        .ok_or_else(|| Error::event_not_found(id))?;
+    if !is_allowed(&event, caller, action) {
+        return Err(Error::NotAuthorized {
+            msg: format!("You're not allowed to {} the event with id={}", action.describe(), id),
+            caller,
+        });
+    }
+    Ok(event)
+}
+
+// Checks that the caller may grant or revoke `role` on `event`: only roles below
+// their own, and never the owner role
+pub fn authorize_role_change(event: &Event, role: Role) -> Result<(), Error> {
+    let caller = caller();
+    if !may_change_role(roles::role_of(event, caller), role) {
+        return Err(Error::NotAuthorized {
+            msg: format!("You're not allowed to manage {:?} roles of the event with id={}", role, event.id),
+            caller,
+        });
+    }
+    Ok(())
+}
+
+// Whether a principal holding `own` may grant or revoke `role`
+fn may_change_role(own: Option<Role>, role: Role) -> bool {
+    role != Role::Owner && own.is_some_and(|own| own < role)
+}
+
+// Checks that `event` is still at the version the caller last saw
+pub fn check_version(event: &Event, expected_version: u64) -> Result<(), Error> {
+    if event.version != expected_version {
+        return Err(Error::Conflict {
+            msg: format!(
+                "The event with id={} is at version {}, not {}",
+                event.id, event.version, expected_version
+            ),
+            current_version: event.version,
+        });
+    }
+    Ok(())
+}
+
+// Checks that the caller is a canister admin and returns them
+pub fn authorize_admin() -> Result<Principal, Error> {
+    let caller = caller();
+    if !admin::is_admin(caller) {
+        return Err(Error::NotAuthorized {
+            msg: "Only canister admins may do this".to_string(),
+            caller,
+        });
+    }
+    Ok(caller)
+}
+
+// Checks that the caller has not been banned from creating events
+pub fn check_not_banned() -> Result<(), Error> {
+    let caller = caller();
+    if admin::is_banned(caller) {
+        return Err(Error::NotAuthorized {
+            msg: "You're banned from creating events".to_string(),
+            caller,
+        });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HOLDERS: [Option<Role>; 5] = [
+        Some(Role::Owner),
+        Some(Role::CoHost),
+        Some(Role::Moderator),
+        Some(Role::CheckInStaff),
+        None,
+    ];
+
+    // The permission matrix above, by Owner, CoHost, Moderator, CheckInStaff, anyone
+    const MATRIX: [(Action, [bool; 5]); 9] = [
+        (Action::Update, [true, true, false, false, false]),
+        (Action::Delete, [true, false, false, false, false]),
+        (Action::TransferOwnership, [true, false, false, false, false]),
+        (Action::RollBack, [true, false, false, false, false]),
+        (Action::ManageAttendees, [true, true, true, false, false]),
+        (Action::CheckIn, [true, true, true, true, false]),
+        (Action::ManageRoles, [true, true, false, false, false]),
+        (Action::ViewHistory, [true, true, true, false, false]),
+        (Action::Attend, [true, true, true, true, true]),
+    ];
+
+    fn principal(n: u8) -> Principal {
+        Principal::from_slice(&[n])
+    }
+
+    #[test]
+    fn roles_follow_the_permission_matrix() {
+        let event = Event {
+            owner: principal(0),
+            ..crate::test_event(1)
+        };
+        for (n, role) in HOLDERS.iter().enumerate().skip(1) {
+            if let Some(role) = role {
+                roles::grant(event.id, principal(n as u8), *role, 0);
+            }
+        }
+        for (action, allowed) in MATRIX {
+            for (n, role) in HOLDERS.iter().enumerate() {
+                assert_eq!(
+                    is_allowed(&event, principal(n as u8), action),
+                    allowed[n],
+                    "{:?} may {}: {}",
+                    role,
+                    action.describe(),
+                    allowed[n]
+                );
+            }
+        }
+        // Roles hold for the event they were granted on only
+        let other = Event {
+            owner: principal(0),
+            ..crate::test_event(2)
+        };
+        assert!(!is_allowed(&other, principal(1), Action::Update));
+    }
+
+    #[test]
+    fn only_the_named_principal_is_a_transfer_recipient() {
+        let event = Event {
+            owner: principal(0),
+            hidden_at: Some(1),
+            pending_transfer: Some(crate::ownership::OwnershipTransfer {
+                to: principal(1),
+                proposed_at: 0,
+                expires_at: 10,
+            }),
+            ..crate::test_event(1)
+        };
+        assert!(is_transfer_recipient(&event, principal(1)));
+        assert!(!is_transfer_recipient(&event, principal(2)));
+    }
+
+    #[test]
+    fn roles_are_managed_from_above() {
+        // Owner, CoHost, Moderator, CheckInStaff, anyone changing each role
+        let expected = [
+            (Role::Owner, [false; 5]),
+            (Role::CoHost, [true, false, false, false, false]),
+            (Role::Moderator, [true, true, false, false, false]),
+            (Role::CheckInStaff, [true, true, true, false, false]),
+        ];
+        for (role, allowed) in expected {
+            for (n, own) in HOLDERS.iter().enumerate() {
+                assert_eq!(
+                    may_change_role(*own, role),
+                    allowed[n],
+                    "{:?} may change {:?}: {}",
+                    own,
+                    role,
+                    allowed[n]
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,213 @@
+// Read-only HTTP interface for plain web clients and calendar apps.
+//
+// `http_request` serves, to GET requests only:
+//
+//   /events                  ?limit=&cursor=&owner=     events by id
+//   /events/{id}                                        a single event
+//   /events/{id}/attendees   ?limit=&cursor=&occurrence= attendees of an event
+//   /events/{id}.ics                                    a single event as iCalendar
+//   /calendars/attendee/{token}.ics                     events a principal attends
+//   /calendars/owner/{principal}.ics                    events of an owner
+//
+// The `.ics` routes return `text/calendar` documents calendar apps can subscribe
+// to (see `ical`); attendee calendars are named by the secret token their principal
+// issued, since which events someone attends is private. Everything else is JSON.
+// JSON bodies are the JSON form of the candid types, with principals in their
+// textual form. Pages carry a `next_cursor` to pass back as `cursor`. Errors are sent as
+// `{"code": 1001, "error": "NotFound", "message": "..."}` with a matching HTTP
+// status. Requests arrive as anonymous queries, so hidden events are not found.
+//
+// Responses are not certified; on mainnet they are served through the `raw`
+// gateway domain, e.g. https://<canister id>.raw.icp0.io/events.
+
+use candid::{CandidType, Principal};
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+use crate::{attendance, ical, listing, Error, Event, _get_visible_event};
+
+// Page size used when the query string does not ask for one
+const DEFAULT_PAGE_SIZE: u32 = 20;
+
+const JSON_CONTENT_TYPE: &str = "application/json; charset=utf-8";
+const CALENDAR_CONTENT_TYPE: &str = "text/calendar; charset=utf-8";
+
+type HeaderField = (String, String);
+
+#[derive(CandidType, Deserialize)]
+pub struct HttpRequest {
+    pub method: String,
+    pub url: String,
+    pub headers: Vec<HeaderField>,
+    pub body: Vec<u8>,
+}
+
+#[derive(CandidType, Deserialize)]
+pub struct HttpResponse {
+    pub status_code: u16,
+    pub headers: Vec<HeaderField>,
+    pub body: Vec<u8>,
+}
+
+// Body of GET /events
+#[derive(Serialize)]
+struct EventsBody {
+    events: Vec<Event>,
+    // Id of the last event read, to pass as `cursor`
+    next_cursor: Option<u64>,
+}
+
+// Body of every error response
+#[derive(Serialize)]
+struct ErrorBody<'a> {
+    code: u16,
+    error: &'a str,
+    message: &'a str,
+}
+
+// Answers a request from the HTTP gateway
+pub fn handle(request: HttpRequest) -> HttpResponse {
+    if request.method != "GET" {
+        let mut response = error_response(&Error::InvalidInput {
+            msg: format!("method {} is not allowed; only GET is", request.method),
+            fields: Vec::new(),
+        });
+        response.status_code = 405;
+        response.headers.push(("Allow".to_string(), "GET".to_string()));
+        return response;
+    }
+
+    let (path, query) = request.url.split_once('?').unwrap_or((&request.url, ""));
+    let query = Query(query);
+    let segments: Vec<&str> = path.split('/').filter(|segment| !segment.is_empty()).collect();
+    let result = match segments.as_slice() {
+        ["events"] => list_events(&query).map(json_response),
+        ["events", file] if file.ends_with(".ics") => parse_id(file.trim_end_matches(".ics"))
+            .and_then(ical::for_event)
+            .map(calendar_response),
+        ["events", id] => parse_id(id).and_then(get_event).map(json_response),
+        ["events", id, "attendees"] => parse_id(id)
+            .and_then(|id| get_attendees(id, &query))
+            .map(json_response),
+        ["calendars", "attendee", file] => ical::token_owner(file)
+            .ok_or_else(|| not_found(path))
+            .map(ical::for_attendee)
+            .map(calendar_response),
+        ["calendars", "owner", file] => parse_calendar_principal(file)
+            .map(ical::for_owner)
+            .map(calendar_response),
+        _ => Err(not_found(path)),
+    };
+    result.unwrap_or_else(|error| error_response(&error))
+}
+
+fn list_events(query: &Query) -> Result<Vec<u8>, Error> {
+    let cursor: Option<u64> = query.get("cursor")?;
+    let response = listing::list(&listing::ListRequest {
+        cursor: cursor.map(|id| listing::ListCursor {
+            sort_value: id,
+            start_after: id,
+        }),
+        limit: Some(query.get("limit")?.unwrap_or(DEFAULT_PAGE_SIZE)),
+        owner: query.get("owner")?,
+        ..Default::default()
+    });
+    to_json(&EventsBody {
+        events: response.events,
+        next_cursor: response.next_cursor.map(|cursor| cursor.start_after),
+    })
+}
+
+fn get_event(id: u64) -> Result<Vec<u8>, Error> {
+    to_json(&_get_visible_event(id)?)
+}
+
+fn get_attendees(id: u64, query: &Query) -> Result<Vec<u8>, Error> {
+    let event = _get_visible_event(id)?;
+    let slot = attendance::resolve_slot(&event, query.get("occurrence")?)?;
+    let cursor: Option<Principal> = query.get("cursor")?;
+    let limit = query.get("limit")?.unwrap_or(DEFAULT_PAGE_SIZE);
+    to_json(&attendance::page(slot, cursor, limit))
+}
+
+fn parse_id(id: &str) -> Result<u64, Error> {
+    id.parse().map_err(|_| Error::NotFound {
+        msg: format!("{} is not an event id", id),
+    })
+}
+
+// Principal named by a `{principal}.ics` path segment
+fn parse_calendar_principal(file: &str) -> Result<Principal, Error> {
+    file.strip_suffix(".ics")
+        .and_then(|text| Principal::from_text(text).ok())
+        .ok_or_else(|| not_found(file))
+}
+
+fn not_found(path: &str) -> Error {
+    Error::NotFound {
+        msg: format!("no route for {}", path),
+    }
+}
+
+// Query string of a request
+struct Query<'a>(&'a str);
+
+impl Query<'_> {
+    // Parses the value of parameter `name`, if present
+    fn get<T: FromStr>(&self, name: &str) -> Result<Option<T>, Error> {
+        let value = self
+            .0
+            .split('&')
+            .filter_map(|pair| pair.split_once('='))
+            .find(|(key, _)| *key == name)
+            .map(|(_, value)| value);
+        match value {
+            Some(value) => value
+                .parse()
+                .map(Some)
+                .map_err(|_| Error::invalid_field(name, format!("cannot parse {:?}", value))),
+            None => Ok(None),
+        }
+    }
+}
+
+fn to_json(value: &impl Serialize) -> Result<Vec<u8>, Error> {
+    serde_json::to_vec(value).map_err(|e| Error::Internal {
+        msg: format!("cannot encode the response: {}", e),
+    })
+}
+
+fn json_response(body: Vec<u8>) -> HttpResponse {
+    response(200, JSON_CONTENT_TYPE, body)
+}
+
+fn calendar_response(calendar: String) -> HttpResponse {
+    response(200, CALENDAR_CONTENT_TYPE, calendar.into_bytes())
+}
+
+fn response(status_code: u16, content_type: &str, body: Vec<u8>) -> HttpResponse {
+    HttpResponse {
+        status_code,
+        headers: vec![
+            ("Content-Type".to_string(), content_type.to_string()),
+            ("Content-Length".to_string(), body.len().to_string()),
+            ("Access-Control-Allow-Origin".to_string(), "*".to_string()),
+        ],
+        body,
+    }
+}
+
+fn error_response(error: &Error) -> HttpResponse {
+    let (code, name, msg) = error.parts();
+    let body = ErrorBody {
+        code,
+        error: name,
+        message: msg,
+    };
+    // An error body of three plain fields always encodes
+    response(
+        error.http_status(),
+        JSON_CONTENT_TYPE,
+        serde_json::to_vec(&body).unwrap_or_default(),
+    )
+}
@@ -0,0 +1,265 @@
+// iCalendar (RFC 5545) export.
+//
+// Calendars are built for a single event, for the events a principal holds a
+// seat in and for the events of an owner. Times are written in UTC, which is
+// also how recurrence rules are expanded, so an event's `RRULE` and `EXDATE`
+// describe exactly the occurrences get_event_occurrences returns. A seat in a
+// single occurrence of a recurring event becomes a VEVENT of its own, with a
+// UID naming the occurrence.
+//
+// Events without a schedule have no DTSTART and are left out. A calendar stops
+// before the event that would take it past `MAX_CALENDAR_BYTES`, so it always
+// fits in a response.
+//
+// Which events a principal attends is private: their calendar is served to
+// themselves and to admins, and over HTTP only under a secret token they issue
+// (and can replace) with rotate_attendee_calendar_token.
+
+use candid::Principal;
+
+use crate::attendance::{self, WHOLE_EVENT};
+use crate::recurrence::{self, Frequency, Weekday};
+use crate::{
+    listing, Error, Event, StorablePrincipal, CALENDAR_TOKENS, CALENDAR_TOKEN_OF,
+    _get_visible_event,
+};
+
+const NANOS_PER_SECOND: u64 = 1_000_000_000;
+const SECONDS_PER_DAY: u64 = 86_400;
+
+// Most events written into a single calendar
+const MAX_CALENDAR_EVENTS: usize = 500;
+
+// Largest calendar served, well below the response size limit
+const MAX_CALENDAR_BYTES: usize = 1_500_000;
+
+// Secret naming the attendee calendar of a principal in its subscription URL
+pub type CalendarToken = [u8; 32];
+
+// Longest content line in octets before it is folded
+const MAX_LINE_OCTETS: usize = 75;
+
+// Calendar for the event with the given id, if the caller may see it
+pub fn for_event(id: u64) -> Result<String, Error> {
+    let event = _get_visible_event(id)?;
+    let name = event.event_title.clone();
+    Ok(calendar(&name, &[(event, WHOLE_EVENT)]))
+}
+
+// Calendar of the events, or single occurrences, `principal` holds a seat in
+pub fn for_attendee(principal: Principal) -> String {
+    // Seats in trashed or hidden events are skipped before the limit applies
+    let entries = attendance::seats_of(principal, MAX_CALENDAR_EVENTS, |(id, occurrence)| {
+        Some((_get_visible_event(id).ok()?, occurrence))
+    });
+    calendar(&format!("Events attended by {}", principal), &entries)
+}
+
+// Calendar of the events owned by `owner`
+pub fn for_owner(owner: Principal) -> String {
+    let entries: Vec<(Event, u64)> = listing::owned_by(owner, MAX_CALENDAR_EVENTS)
+        .into_iter()
+        .map(|event| (event, WHOLE_EVENT))
+        .collect();
+    calendar(&format!("Events of {}", owner), &entries)
+}
+
+// Calendar holding the given events, each paired with the occurrence to write
+// (`WHOLE_EVENT` for the whole series)
+fn calendar(name: &str, entries: &[(Event, u64)]) -> String {
+    let mut out = String::new();
+    push_line(&mut out, "BEGIN:VCALENDAR");
+    push_line(&mut out, "VERSION:2.0");
+    push_line(&mut out, "PRODID:-//icp_rust_event_contract//Events//EN");
+    push_line(&mut out, "CALSCALE:GREGORIAN");
+    push_line(&mut out, "METHOD:PUBLISH");
+    push_line(&mut out, &format!("X-WR-CALNAME:{}", escape(name)));
+    let end = "END:VCALENDAR\r\n";
+    for (event, occurrence) in entries {
+        let mut vevent = String::new();
+        push_event(&mut vevent, event, *occurrence);
+        if out.len() + vevent.len() + end.len() > MAX_CALENDAR_BYTES {
+            break;
+        }
+        out.push_str(&vevent);
+    }
+    out.push_str(end);
+    out
+}
+
+// Gives `principal` a new attendee calendar token, which replaces the previous one
+pub fn set_token(principal: Principal, token: CalendarToken) -> String {
+    let key = StorablePrincipal(principal);
+    if let Some(previous) = CALENDAR_TOKEN_OF.with(|t| t.borrow_mut().insert(key, token)) {
+        CALENDAR_TOKENS.with(|t| t.borrow_mut().remove(&previous));
+    }
+    CALENDAR_TOKENS.with(|t| t.borrow_mut().insert(token, key));
+    token.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+// Principal whose attendee calendar the token in a `{token}.ics` file name opens
+pub fn token_owner(file: &str) -> Option<Principal> {
+    let text = file.strip_suffix(".ics")?;
+    if text.len() != 64 || !text.bytes().all(|b| b.is_ascii_digit() || (b'a'..=b'f').contains(&b)) {
+        return None;
+    }
+    let mut token: CalendarToken = [0; 32];
+    for (i, byte) in token.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&text[2 * i..2 * i + 2], 16).ok()?;
+    }
+    CALENDAR_TOKENS.with(|t| t.borrow().get(&token)).map(|principal| principal.0)
+}
+
+fn push_event(out: &mut String, event: &Event, occurrence: u64) {
+    let (Some(starts_at), Some(ends_at)) = (event.starts_at, event.ends_at) else {
+        return;
+    };
+    let canister = ic_cdk::id();
+    let whole = occurrence == WHOLE_EVENT || event.recurrence.is_none();
+    let (uid, starts_at, ends_at) = if whole {
+        (format!("{}@{}", event.id, canister), starts_at, ends_at)
+    } else {
+        let duration = ends_at.saturating_sub(starts_at);
+        let uid = format!("{}-{}@{}", event.id, occurrence, canister);
+        (uid, occurrence, occurrence.saturating_add(duration))
+    };
+
+    push_line(out, "BEGIN:VEVENT");
+    push_line(out, &format!("UID:{}", uid));
+    let stamp = event.updated_at.unwrap_or(event.created_at);
+    push_line(out, &format!("DTSTAMP:{}", format_time(stamp)));
+    push_line(out, &format!("CREATED:{}", format_time(event.created_at)));
+    if let Some(updated_at) = event.updated_at {
+        push_line(out, &format!("LAST-MODIFIED:{}", format_time(updated_at)));
+    }
+    push_line(out, &format!("SEQUENCE:{}", event.version));
+    push_line(out, &format!("DTSTART:{}", format_time(starts_at)));
+    push_line(out, &format!("DTEND:{}", format_time(ends_at)));
+    if let Some(rule) = event.recurrence.as_ref().filter(|_| whole) {
+        push_line(out, &format!("RRULE:{}", rrule(rule)));
+        for exception in &rule.exceptions {
+            push_line(out, &format!("EXDATE:{}", format_time(*exception)));
+        }
+    }
+    push_line(out, &format!("SUMMARY:{}", escape(&event.event_title)));
+    if !event.event_description.is_empty() {
+        push_line(out, &format!("DESCRIPTION:{}", escape(&event.event_description)));
+    }
+    if !event.event_location.is_empty() {
+        push_line(out, &format!("LOCATION:{}", escape(&event.event_location)));
+    }
+    if !event.event_card_imgurl.is_empty() {
+        push_line(out, &format!("IMAGE;VALUE=URI:{}", event.event_card_imgurl));
+    }
+    push_line(out, "STATUS:CONFIRMED");
+    push_line(out, "END:VEVENT");
+}
+
+fn rrule(rule: &recurrence::RecurrenceRule) -> String {
+    let frequency = match rule.frequency {
+        Frequency::Daily => "DAILY",
+        Frequency::Weekly => "WEEKLY",
+        Frequency::Monthly => "MONTHLY",
+    };
+    let mut parts = vec![format!("FREQ={}", frequency)];
+    if let Some(interval) = rule.interval.filter(|interval| *interval > 1) {
+        parts.push(format!("INTERVAL={}", interval));
+    }
+    if !rule.by_day.is_empty() {
+        let days: Vec<&str> = rule.by_day.iter().map(|day| weekday_code(*day)).collect();
+        parts.push(format!("BYDAY={}", days.join(",")));
+    }
+    if let Some(count) = rule.count {
+        parts.push(format!("COUNT={}", count));
+    }
+    if let Some(until) = rule.until {
+        parts.push(format!("UNTIL={}", format_time(until)));
+    }
+    parts.join(";")
+}
+
+fn weekday_code(day: Weekday) -> &'static str {
+    match day {
+        Weekday::Monday => "MO",
+        Weekday::Tuesday => "TU",
+        Weekday::Wednesday => "WE",
+        Weekday::Thursday => "TH",
+        Weekday::Friday => "FR",
+        Weekday::Saturday => "SA",
+        Weekday::Sunday => "SU",
+    }
+}
+
+// UTC date-time of a timestamp in nanoseconds, e.g. "20240131T183000Z"
+fn format_time(nanos: u64) -> String {
+    let seconds = nanos / NANOS_PER_SECOND;
+    let (year, month, day) = recurrence::civil_from_days((seconds / SECONDS_PER_DAY) as i64);
+    let time_of_day = seconds % SECONDS_PER_DAY;
+    format!(
+        "{:04}{:02}{:02}T{:02}{:02}{:02}Z",
+        year,
+        month,
+        day,
+        time_of_day / 3600,
+        time_of_day % 3600 / 60,
+        time_of_day % 60
+    )
+}
+
+// Escapes a TEXT value
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '\\' | ';' | ',' => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            '\n' => escaped.push_str("\\n"),
+            '\r' => {}
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+// Appends a content line, folding it every 75 octets without splitting a character
+fn push_line(out: &mut String, line: &str) {
+    let mut width = 0;
+    for c in line.chars() {
+        if width + c.len_utf8() > MAX_LINE_OCTETS {
+            out.push_str("\r\n ");
+            width = 1;
+        }
+        out.push(c);
+        width += c.len_utf8();
+    }
+    out.push_str("\r\n");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tokens_open_the_calendar_of_their_principal_until_replaced() {
+        let principal = Principal::from_slice(&[1]);
+        let first = set_token(principal, [0xab; 32]);
+        assert_eq!(first, "ab".repeat(32));
+        assert_eq!(token_owner(&format!("{}.ics", first)), Some(principal));
+
+        let second = set_token(principal, [0x01; 32]);
+        assert_eq!(token_owner(&format!("{}.ics", second)), Some(principal));
+        assert_eq!(token_owner(&format!("{}.ics", first)), None);
+    }
+
+    #[test]
+    fn rejects_malformed_tokens() {
+        set_token(Principal::from_slice(&[1]), [0xab; 32]);
+        for file in ["ab".repeat(32), "AB".repeat(32) + ".ics", "ab".repeat(31) + ".ics"] {
+            assert_eq!(token_owner(&file), None, "{}", file);
+        }
+        // A principal is no longer accepted in place of a token
+        assert_eq!(token_owner(&format!("{}.ics", Principal::from_slice(&[1]))), None);
+    }
+}
@@ -1,46 +1,122 @@
 #[macro_use]
     extern crate serde;
-    use candid::{Decode, Encode};
     use ic_cdk::api::time;
+    use ic_cdk::api::management_canister::main::raw_rand;
     use ic_stable_structures::memory_manager::{MemoryId, MemoryManager, VirtualMemory};
     use ic_stable_structures::{BoundedStorable, Cell, DefaultMemoryImpl, StableBTreeMap, Storable};
     use std::{borrow::Cow, cell::RefCell};
     use ic_cdk::caller;
     use candid::Principal;
+    use error::Error;
+    use guard::reject_anonymous;
+
+    mod admin;
+    mod attendance;
+    mod audit;
+    mod bulk;
+    mod certification;
+    mod error;
+    mod guard;
+    mod http;
+    mod ical;
+    mod listing;
+    mod migrations;
+    mod ownership;
+    mod recurrence;
+    mod revisions;
+    mod roles;
+    mod search;
+    mod trash;
+    mod validation;
 
 
     type Memory = VirtualMemory<DefaultMemoryImpl>;
     type IdCell = Cell<u64, Memory>;
 
     
-    // Define the Event struct with CandidType, Clone, Serialize, Deserialize, and Default traits
-    #[derive(candid::CandidType, Clone, Serialize, Deserialize, Default)]
+    // Define the Event struct with CandidType, Clone, Serialize and Deserialize traits
+    #[derive(candid::CandidType, Clone, Serialize, Deserialize)]
     struct Event {
         id: u64,
         event_description: String,
-        owner: String,
+        owner: Principal,
         event_title: String,
         event_location : String,
         event_card_imgurl : String,
-        attendees : Vec<String>,
+        attendee_count : u64,
+        // Most attendees per occurrence; further callers join the waitlist. Unlimited when unset
+        capacity: Option<u32>,
+        // When the event takes place, in nanoseconds since the epoch; unset on events
+        // created before scheduling was introduced
+        starts_at: Option<u64>,
+        ends_at: Option<u64>,
+        // IANA time zone name the organizer scheduled the event in, e.g. "Africa/Lagos"
+        time_zone: Option<String>,
+        // Repeats the starts_at..ends_at occurrence; unset for one-off events
+        recurrence: Option<recurrence::RecurrenceRule>,
+        // Ownership transfer waiting for the proposed owner to accept it
+        pending_transfer: Option<ownership::OwnershipTransfer>,
+        // Earlier owners, oldest first; unset on events that never changed hands
+        previous_owners: Option<Vec<ownership::PreviousOwner>>,
+        // When a canister admin hid the event; hidden events are only visible to its staff and admins
+        hidden_at: Option<u64>,
+        // When the event was moved to the trash; only set on events in `TRASH`
+        deleted_at: Option<u64>,
         created_at: u64,
         updated_at: Option<u64>,
+        // Raised on every change to the event's details or ownership, but not on attendance
+        // changes; updates and deletes name the version they expect
+        version: u64,
     }
 
-     // a trait that must be implemented for a struct that is stored in a stable struct
+     // a trait that must be implemented for a struct that is stored in a stable struct;
+     // events are wrapped in a schema-versioned envelope so older records survive upgrades
      impl Storable for Event {
-        fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
-            Cow::Owned(Encode!(self).unwrap())
+        fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+            Cow::Owned(migrations::encode_event(self))
         }
     
         fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
-            Decode!(bytes.as_ref(), Self).unwrap()
+            migrations::decode_event(bytes.as_ref()).unwrap_or_else(|msg| ic_cdk::trap(&msg))
         }
     }
     
-    // another trait that must be implemented for a struct that is stored in a stable struct
+    // another trait that must be implemented for a struct that is stored in a stable struct;
+    // attendees live in their own map, so this only has to fit the event body
     impl BoundedStorable for Event {
-        const MAX_SIZE: u32 = 1024;
+        const MAX_SIZE: u32 = 16 * 1024;
+        const IS_FIXED_SIZE: bool = false;
+    }
+
+    // Part of `Event::MAX_SIZE` kept free when an event's details are set, so the fields
+    // written later (moderation and trash timestamps, transfers, up to 20 previous owners)
+    // still fit
+    const EVENT_SIZE_HEADROOM: u32 = 2 * 1024;
+
+
+    // Principal wrapper so principals can be used inside stable structure keys
+    #[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+    struct StorablePrincipal(Principal);
+
+    // Required by the tuple `Storable` impl; the anonymous principal is never stored
+    impl Default for StorablePrincipal {
+        fn default() -> Self {
+            Self(Principal::anonymous())
+        }
+    }
+
+    impl Storable for StorablePrincipal {
+        fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+            Cow::Borrowed(self.0.as_slice())
+        }
+
+        fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+            Self(Principal::from_slice(bytes.as_ref()))
+        }
+    }
+
+    impl BoundedStorable for StorablePrincipal {
+        const MAX_SIZE: u32 = 29;
         const IS_FIXED_SIZE: bool = false;
     }
 
@@ -57,10 +133,241 @@
         );
 
     
-        static STORAGE: RefCell<StableBTreeMap<u64, Event, Memory>> =
+        // Events written before attendees were split out; drained by the v3 migration
+        static LEGACY_STORAGE: RefCell<StableBTreeMap<u64, migrations::LegacyEventBytes, Memory>> =
             RefCell::new(StableBTreeMap::init(
                 MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(1)))
         ));
+
+        static STORAGE: RefCell<StableBTreeMap<u64, Event, Memory>> =
+            RefCell::new(StableBTreeMap::init(
+                MEMORY_MANAGER.with(|m| {
+                    m.borrow().get(MemoryId::new(migrations::STORAGE_MEMORY_ID))
+                })
+        ));
+
+        // (event id, attendee) entries written before per-occurrence attendance; drained by the v6 migration
+        static LEGACY_ATTENDEES: RefCell<StableBTreeMap<(u64, StorablePrincipal), u64, Memory>> =
+            RefCell::new(StableBTreeMap::init(
+                MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(4)))
+        ));
+
+        static ATTENDEES: RefCell<StableBTreeMap<attendance::AttendeeKey, u64, Memory>> =
+            RefCell::new(StableBTreeMap::init(
+                MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(10)))
+        ));
+
+        // Number of attendees per (event id, occurrence) slot
+        static SLOT_COUNTS: RefCell<StableBTreeMap<attendance::Slot, u64, Memory>> =
+            RefCell::new(StableBTreeMap::init(
+                MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(11)))
+        ));
+
+        // (created_at, id) and (updated_at, id) indexes used by list_events
+        static CREATED_INDEX: RefCell<StableBTreeMap<(u64, u64), (), Memory>> =
+            RefCell::new(StableBTreeMap::init(
+                MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(5)))
+        ));
+
+        static UPDATED_INDEX: RefCell<StableBTreeMap<(u64, u64), (), Memory>> =
+            RefCell::new(StableBTreeMap::init(
+                MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(6)))
+        ));
+
+        // Inverted (token, id) -> weight index used by search_events
+        static SEARCH_INDEX: RefCell<StableBTreeMap<(search::Token, u64), u32, Memory>> =
+            RefCell::new(StableBTreeMap::init(
+                MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(7)))
+        ));
+
+        // (starts_at, id) and (ends_at, id) indexes over scheduled events
+        static STARTS_INDEX: RefCell<StableBTreeMap<(u64, u64), (), Memory>> =
+            RefCell::new(StableBTreeMap::init(
+                MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(8)))
+        ));
+
+        static ENDS_INDEX: RefCell<StableBTreeMap<(u64, u64), (), Memory>> =
+            RefCell::new(StableBTreeMap::init(
+                MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(9)))
+        ));
+
+        // (slot, sequence number) -> (principal, joined_at) waitlist entries, in join order
+        static WAITLIST: RefCell<StableBTreeMap<attendance::WaitlistKey, (StorablePrincipal, u64), Memory>> =
+            RefCell::new(StableBTreeMap::init(
+                MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(12)))
+        ));
+
+        // (slot, principal) -> sequence number of the principal's waitlist entry
+        static WAITLIST_ENTRIES: RefCell<StableBTreeMap<attendance::AttendeeKey, u64, Memory>> =
+            RefCell::new(StableBTreeMap::init(
+                MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(13)))
+        ));
+
+        // (slot, principal) -> the principal's RSVP status and timestamps
+        static RSVPS: RefCell<StableBTreeMap<attendance::AttendeeKey, attendance::Rsvp, Memory>> =
+            RefCell::new(StableBTreeMap::init(
+                MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(15)))
+        ));
+
+        // (slot, principal) -> time the attendee was checked in
+        static CHECK_INS: RefCell<StableBTreeMap<attendance::AttendeeKey, u64, Memory>> =
+            RefCell::new(StableBTreeMap::init(
+                MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(17)))
+        ));
+
+        // (event id, principal) -> role granted on the event; owners are implied by Event.owner
+        static ROLES: RefCell<StableBTreeMap<roles::RoleKey, roles::Grant, Memory>> =
+            RefCell::new(StableBTreeMap::init(
+                MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(16)))
+        ));
+
+        // Canister admins and the time they were added
+        static ADMINS: RefCell<StableBTreeMap<StorablePrincipal, u64, Memory>> =
+            RefCell::new(StableBTreeMap::init(
+                MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(18)))
+        ));
+
+        // Principals banned from creating events and the time they were banned
+        static BANNED: RefCell<StableBTreeMap<StorablePrincipal, u64, Memory>> =
+            RefCell::new(StableBTreeMap::init(
+                MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(19)))
+        ));
+
+        // Sequence number -> moderation action, in the order the actions were taken
+        static MODERATION_LOG: RefCell<StableBTreeMap<u64, admin::ModerationAction, Memory>> =
+            RefCell::new(StableBTreeMap::init(
+                MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(20)))
+        ));
+
+        static VALIDATION_LIMITS: RefCell<Cell<validation::ValidationLimits, Memory>> = RefCell::new(
+            Cell::init(
+                MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(21))),
+                validation::ValidationLimits::default(),
+            )
+            .expect("Cannot create the validation limits")
+        );
+
+        // Soft-deleted events waiting to be restored or purged
+        static TRASH: RefCell<StableBTreeMap<u64, Event, Memory>> =
+            RefCell::new(StableBTreeMap::init(
+                MEMORY_MANAGER.with(|m| {
+                    m.borrow().get(MemoryId::new(migrations::TRASH_MEMORY_ID))
+                })
+        ));
+
+        // (purge_at, id) index over the trash, soonest purge first
+        static TRASH_EXPIRY: RefCell<StableBTreeMap<(u64, u64), (), Memory>> =
+            RefCell::new(StableBTreeMap::init(
+                MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(23)))
+        ));
+
+        // (owner, id) index over the trash used by list_trash
+        static TRASH_BY_OWNER: RefCell<StableBTreeMap<(StorablePrincipal, u64), (), Memory>> =
+            RefCell::new(StableBTreeMap::init(
+                MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(24)))
+        ));
+
+        // Append-only log of event mutations, indexed by event and by caller below
+        static AUDIT_LOG: RefCell<ic_stable_structures::Log<audit::AuditEntry, Memory, Memory>> = RefCell::new(
+            ic_stable_structures::Log::init(
+                MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(25))),
+                MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(26))),
+            )
+            .expect("Cannot create the audit log")
+        );
+
+        static AUDIT_BY_EVENT: RefCell<StableBTreeMap<(u64, u64), (), Memory>> =
+            RefCell::new(StableBTreeMap::init(
+                MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(27)))
+        ));
+
+        static AUDIT_BY_PRINCIPAL: RefCell<StableBTreeMap<(StorablePrincipal, u64), (), Memory>> =
+            RefCell::new(StableBTreeMap::init(
+                MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(28)))
+        ));
+
+        // Earlier states of edited events, keyed by (event id, version)
+        static REVISIONS: RefCell<StableBTreeMap<(u64, u64), revisions::Revision, Memory>> =
+            RefCell::new(StableBTreeMap::init(
+                MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(29)))
+        ));
+
+        // (principal, slot) mirror of `ATTENDEES` listing the seats a principal holds
+        static SEATS_BY_PRINCIPAL: RefCell<StableBTreeMap<(StorablePrincipal, attendance::Slot), (), Memory>> =
+            RefCell::new(StableBTreeMap::init(
+                MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(31)))
+        ));
+
+        // (owner, id) index of stored events, backing the owner calendars
+        static OWNER_INDEX: RefCell<StableBTreeMap<(StorablePrincipal, u64), (), Memory>> =
+            RefCell::new(StableBTreeMap::init(
+                MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(32)))
+        ));
+
+        // Attendee calendar tokens and the principal whose calendar each opens
+        static CALENDAR_TOKENS: RefCell<StableBTreeMap<ical::CalendarToken, StorablePrincipal, Memory>> =
+            RefCell::new(StableBTreeMap::init(
+                MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(34)))
+        ));
+
+        // Current attendee calendar token of each principal that issued one
+        static CALENDAR_TOKEN_OF: RefCell<StableBTreeMap<StorablePrincipal, ical::CalendarToken, Memory>> =
+            RefCell::new(StableBTreeMap::init(
+                MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(35)))
+        ));
+
+        // Records set aside by a migration because they no longer decode, kept for recovery
+        static UNREADABLE_EVENTS: RefCell<StableBTreeMap<u64, migrations::EventBytes, Memory>> =
+            RefCell::new(StableBTreeMap::init(
+                MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(33)))
+        ));
+
+        // Hashes of the non-empty nodes of the certified event tree, keyed by (depth, id prefix)
+        static CERT_TREE: RefCell<StableBTreeMap<(u8, u64), [u8; 32], Memory>> =
+            RefCell::new(StableBTreeMap::init(
+                MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(30)))
+        ));
+
+        static WAITLIST_SEQ: RefCell<IdCell> = RefCell::new(
+            IdCell::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(14))), 0)
+                .expect("Cannot create the waitlist sequence")
+        );
+
+        static SCHEMA_STATE: RefCell<Cell<migrations::SchemaState, Memory>> = RefCell::new(
+            Cell::init(
+                MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(2))),
+                migrations::SchemaState::default(),
+            )
+            .expect("Cannot create the schema state")
+        );
+    }
+
+
+    // Marks a fresh install as already being at the current storage schema and makes the
+    // installing controller the first canister admin
+    #[ic_cdk::init]
+    fn init() {
+        migrations::init_schema();
+        certification::publish();
+        admin::seed(caller(), time());
+    }
+
+
+    // Rewrites events stored by a previous version of the canister into the current schema;
+    // canisters installed before admins existed get the upgrading controller as their first admin
+    #[ic_cdk::post_upgrade]
+    fn post_upgrade() {
+        migrations::run();
+        certification::publish();
+        admin::seed(caller(), time());
+        trash::schedule_purge();
+    }
+
+
+    // Query function reporting the storage schema version and the latest migration run
+    #[ic_cdk::query]
+    fn get_schema_info() -> migrations::SchemaInfo {
+        migrations::schema_info()
     }
 
 
@@ -71,6 +378,124 @@
         event_title: String,
         event_location : String,
         event_card_imgurl : String,
+        // Start and end of the event in nanoseconds since the epoch
+        starts_at: u64,
+        ends_at: u64,
+        time_zone: String,
+        recurrence: Option<recurrence::RecurrenceRule>,
+        capacity: Option<u32>,
+    }
+
+    // Partial update of an Event; unset fields keep their current value. `recurrence` and
+    // `capacity` take `opt null` to clear them
+    #[derive(candid::CandidType, Serialize, Deserialize, Default)]
+    struct EventPatch {
+        event_description: Option<String>,
+        event_title: Option<String>,
+        event_location : Option<String>,
+        event_card_imgurl : Option<String>,
+        starts_at: Option<u64>,
+        ends_at: Option<u64>,
+        time_zone: Option<String>,
+        recurrence: Option<Option<recurrence::RecurrenceRule>>,
+        capacity: Option<Option<u32>>,
+    }
+
+    impl EventPatch {
+        // Whether the patch sets a field of the schedule
+        fn sets_schedule(&self) -> bool {
+            self.starts_at.is_some()
+                || self.ends_at.is_some()
+                || self.time_zone.is_some()
+                || self.recurrence.is_some()
+        }
+
+        // Full payload made of the patched fields and the current values of the others
+        fn apply_to(self, event: &Event) -> EventPayload {
+            EventPayload {
+                event_description: self.event_description.unwrap_or_else(|| event.event_description.clone()),
+                event_title: self.event_title.unwrap_or_else(|| event.event_title.clone()),
+                event_location: self.event_location.unwrap_or_else(|| event.event_location.clone()),
+                event_card_imgurl: self.event_card_imgurl.unwrap_or_else(|| event.event_card_imgurl.clone()),
+                starts_at: self.starts_at.or(event.starts_at).unwrap_or_default(),
+                ends_at: self.ends_at.or(event.ends_at).unwrap_or_default(),
+                time_zone: self.time_zone.or_else(|| event.time_zone.clone()).unwrap_or_default(),
+                recurrence: self.recurrence.unwrap_or_else(|| event.recurrence.clone()),
+                capacity: self.capacity.unwrap_or(event.capacity),
+            }
+        }
+    }
+
+    // Outcome of attend_event: the event and, if it was full, the caller's waitlist position
+    #[derive(candid::CandidType, Serialize, Deserialize)]
+    struct AttendResponse {
+        event: Event,
+        waitlist_position: Option<u64>,
+    }
+
+    // Top-level IANA time zone areas accepted besides the plain "UTC"/"GMT" names
+    const TIME_ZONE_AREAS: [&str; 11] = [
+        "Africa", "America", "Antarctica", "Arctic", "Asia", "Atlantic",
+        "Australia", "Europe", "Indian", "Pacific", "Etc",
+    ];
+
+    // Helper function checking that a name has the shape of an IANA time zone, e.g. "Europe/Berlin"
+    fn is_valid_time_zone(name: &str) -> bool {
+        if name == "UTC" || name == "GMT" {
+            return true;
+        }
+        let mut parts = name.split('/');
+        let area_known = parts.next().is_some_and(|area| TIME_ZONE_AREAS.contains(&area));
+        let locations: Vec<&str> = parts.collect();
+        area_known
+            && !locations.is_empty()
+            && locations.iter().all(|part| {
+                !part.is_empty()
+                    && part.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '+'))
+            })
+    }
+
+    // Helper function validating the schedule of a payload; new events may not start in the past
+    fn validate_schedule(payload: &EventPayload, is_new: bool) -> Result<(), Error> {
+        if payload.ends_at <= payload.starts_at {
+            return Err(Error::invalid_field("ends_at", "must be after starts_at"));
+        }
+        if is_new && payload.starts_at < time() {
+            return Err(Error::invalid_field("starts_at", "must not be in the past"));
+        }
+        if !is_valid_time_zone(&payload.time_zone) {
+            return Err(Error::invalid_field(
+                "time_zone",
+                format!("{} is not an IANA time zone name", payload.time_zone),
+            ));
+        }
+        if let Some(rule) = &payload.recurrence {
+            recurrence::validate(rule, payload.starts_at)
+                .map_err(|msg| Error::invalid_field("recurrence", msg))?;
+            // Occurrences are expanded in UTC, which would drift across DST changes elsewhere
+            if !recurrence::is_utc(&payload.time_zone) {
+                return Err(Error::invalid_field(
+                    "time_zone",
+                    format!("recurring events must use UTC, not {}", payload.time_zone),
+                ));
+            }
+        }
+        validate_capacity(payload.capacity)
+    }
+
+    // Helper function validating the capacity of a payload
+    fn validate_capacity(capacity: Option<u32>) -> Result<(), Error> {
+        if capacity == Some(0) {
+            return Err(Error::invalid_field("capacity", "must be at least 1"));
+        }
+        Ok(())
+    }
+
+
+    // Query function listing the stable code of every error variant
+    #[ic_cdk::query]
+    fn get_error_codes() -> Vec<error::ErrorCode> {
+        error::codes()
     }
 
 
@@ -78,217 +503,993 @@
     #[ic_cdk::query]
     fn get_event(id: u64) -> Result<Event, Error> {
         
-        // Attempt to retrieve the event using the internal helper function
-        match _get_event(&id) {
+        // Attempt to retrieve the event using the internal helper function; hidden events
+        // are only shown to their staff and to canister admins
+        match _get_event(&id).filter(|event| guard::is_visible(event, caller())) {
             // If the event is found, return it as a Result::Ok
             Some(message) => Ok(message),
 
             // If the event is not found, return a Result::Err with a NotFound error
-            None => Err(Error::NotFound {
-                msg: format!("Event with id={} not found", id),
-            }),
+            None => Err(Error::event_not_found(id)),
         }
     }
 
 
-// Function to get all events created
-#[ic_cdk::query]
-fn get_all_events() -> Result<Vec<Event>, Error> {
-    
-    let events: Vec<(u64, Event)> = STORAGE.with(|service| service.borrow().iter().collect());
-    let event_list: Vec<Event> = events.into_iter().map(|(_, event)| event).collect();
+    // Query function returning an event together with a certificate and a witness that let
+    // the caller check the response against the canister's certified data; must be called
+    // as a query, since replicated calls carry no certificate
+    #[ic_cdk::query]
+    fn get_event_certified(id: u64) -> Result<certification::CertifiedEvent, Error> {
+        let event = _get_visible_event(id)?;
+        certification::certify(event).ok_or_else(|| Error::Internal {
+            msg: "no data certificate is available; call get_event_certified as a query".to_string(),
+        })
+    }
+
+
+    // Query function returning an event as an iCalendar (RFC 5545) document
+    #[ic_cdk::query]
+    fn get_event_ical(id: u64) -> Result<String, Error> {
+        ical::for_event(id)
+    }
+
+
+    // Query function returning an iCalendar document of the events, or occurrences, a
+    // principal holds a seat in; only served to that principal and to admins
+    #[ic_cdk::query]
+    fn get_attendee_ical(principal: Principal) -> Result<String, Error> {
+        let caller = caller();
+        if caller != principal && !admin::is_admin(caller) {
+            return Err(Error::NotAuthorized {
+                msg: "You may only read your own attendee calendar".to_string(),
+                caller,
+            });
+        }
+        Ok(ical::for_attendee(principal))
+    }
+
+
+    // Update function issuing a secret token for the caller's attendee calendar URL,
+    // /calendars/attendee/{token}.ics; the caller's previous token stops working
+    #[ic_cdk::update(guard = "reject_anonymous")]
+    async fn rotate_attendee_calendar_token() -> Result<String, Error> {
+        let caller = caller();
+        let (bytes,) = raw_rand().await.map_err(|(_, msg)| Error::Internal {
+            msg: format!("cannot draw a token: {}", msg),
+        })?;
+        let token = bytes.try_into().map_err(|_| Error::Internal {
+            msg: "cannot draw a token: expected 32 random bytes".to_string(),
+        })?;
+        Ok(ical::set_token(caller, token))
+    }
+
+
+    // Query function returning an iCalendar document of the events a principal owns
+    #[ic_cdk::query]
+    fn get_owner_ical(owner: Principal) -> String {
+        ical::for_owner(owner)
+    }
+
+
+    // Query function serving events as JSON to plain HTTP clients through the HTTP gateway
+    #[ic_cdk::query]
+    fn http_request(request: http::HttpRequest) -> http::HttpResponse {
+        http::handle(request)
+    }
+
+
+    // Query function returning a page of events in the requested order, filtered by owner and creation time
+    #[ic_cdk::query]
+    fn list_events(request: listing::ListRequest) -> listing::ListResponse {
+        listing::list(&request)
+    }
+
+
+    // Query function listing scheduled events whose next occurrence has not started yet
+    #[ic_cdk::query]
+    fn list_upcoming_events(cursor: Option<listing::ListCursor>, limit: Option<u32>) -> listing::ListResponse {
+        listing::upcoming(cursor, limit)
+    }
+
+
+    // Query function listing events with an occurrence taking place right now
+    #[ic_cdk::query]
+    fn list_ongoing_events(cursor: Option<listing::ListCursor>, limit: Option<u32>) -> listing::ListResponse {
+        listing::ongoing(cursor, limit)
+    }
+
+
+    // Query function listing events that have already ended
+    #[ic_cdk::query]
+    fn list_past_events(cursor: Option<listing::ListCursor>, limit: Option<u32>) -> listing::ListResponse {
+        listing::past(cursor, limit)
+    }
+
+
+    // Query function running a ranked prefix search over event titles, descriptions and locations
+    #[ic_cdk::query]
+    fn search_events(query: String, limit: u32, cursor: Option<u64>) -> search::SearchResponse {
+        search::search(&query, limit, cursor)
+    }
+
+
+    // Query function listing the attendees of an event (or of one occurrence of a recurring
+    // event) a page at a time, starting after `cursor`
+    #[ic_cdk::query]
+    fn get_event_attendees(
+        id: u64,
+        cursor: Option<Principal>,
+        limit: u32,
+        occurrence: Option<u64>,
+    ) -> Result<attendance::AttendeePage, Error> {
+        let event = _get_visible_event(id)?;
+        let slot = attendance::resolve_slot(&event, occurrence)?;
+        Ok(attendance::page(slot, cursor, limit))
+    }
+
+
+    // Query function listing the waitlist of an event (or of one occurrence of a recurring
+    // event) in order, skipping the first `cursor` entries
+    #[ic_cdk::query]
+    fn get_event_waitlist(
+        id: u64,
+        cursor: Option<u64>,
+        limit: u32,
+        occurrence: Option<u64>,
+    ) -> Result<attendance::WaitlistPage, Error> {
+        let event = _get_visible_event(id)?;
+        let slot = attendance::resolve_slot(&event, occurrence)?;
+        Ok(attendance::waitlist_page(slot, cursor, limit))
+    }
 
-    // Check if the event_list is not empty.
-    if !event_list.is_empty() {
 
-    // If there are events, return them as Ok(result).
-        Ok(event_list) 
-    } else {
-    // If there are no events, return an Err variant with a custom error.
-        Err(Error::NotFound {
-            msg: format!("no event avaliable"),
-        }) 
+    // Query function returning the caller's 1-based waitlist position, if they are waiting
+    #[ic_cdk::query]
+    fn get_waitlist_position(id: u64, occurrence: Option<u64>) -> Result<Option<u64>, Error> {
+        let event = _get_visible_event(id)?;
+        let slot = attendance::resolve_slot(&event, occurrence)?;
+        Ok(attendance::waitlist_position(slot, caller()))
+    }
+
+
+    // Query function returning the caller's RSVP for an event (or one occurrence), if any
+    #[ic_cdk::query]
+    fn my_rsvp(id: u64, occurrence: Option<u64>) -> Result<Option<attendance::Rsvp>, Error> {
+        let event = _get_visible_event(id)?;
+        let slot = attendance::resolve_slot(&event, occurrence)?;
+        Ok(attendance::rsvp(slot, caller()))
+    }
+
+
+    // Query function listing every principal holding a role on an event, the owner first
+    #[ic_cdk::query]
+    fn list_roles(id: u64) -> Result<Vec<roles::RoleAssignment>, Error> {
+        let event = _get_visible_event(id)?;
+        Ok(roles::list(&event))
+    }
+
+
+    // Most occurrences returned by a single get_event_occurrences call
+    const MAX_OCCURRENCES: usize = 500;
+
+
+    // Query function expanding the occurrences of an event that overlap the [from, to] window
+    #[ic_cdk::query]
+    fn get_event_occurrences(id: u64, from: u64, to: u64) -> Result<Vec<recurrence::Occurrence>, Error> {
+        let event = _get_visible_event(id)?;
+        let duration = match (event.starts_at, event.ends_at) {
+            (Some(starts_at), Some(ends_at)) => ends_at - starts_at,
+            _ => 0,
+        };
+
+        Ok(recurrence::occurrence_starts(&event, from, to, MAX_OCCURRENCES)
+            .into_iter()
+            .map(|starts_at| {
+                let slot = match event.recurrence {
+                    Some(_) => (id, starts_at),
+                    None => (id, attendance::WHOLE_EVENT),
+                };
+                recurrence::Occurrence {
+                    starts_at,
+                    ends_at: starts_at.saturating_add(duration),
+                    attendee_count: attendance::count(slot),
+                }
+            })
+            .collect())
     }
-}
 
     
     // Function to create a new event based on the provided payload
-    #[ic_cdk::update]
-    fn create_event(payload: EventPayload) -> Option<Event> {
-        // Increment the unique identifier for the new event
-        let id = ID_COUNTER
-            .with(|counter| {
-                let current_value = *counter.borrow().get();
-                counter.borrow_mut().set(current_value + 1)
-            })
-            .expect("cannot increment id counter");
+    #[ic_cdk::update(guard = "reject_anonymous")]
+    fn create_event(mut payload: EventPayload) -> Result<Event, Error> {
+        guard::check_not_banned()?;
 
-        // Create a new Event instance with the provided payload and additional details        
-        let event = Event {
-            id,
+        // Reject payloads with invalid fields or schedule before allocating an id
+        validation::validate(&mut payload)?;
+        validate_schedule(&payload, true)?;
+
+        // Create a new Event instance with the provided payload and additional details;
+        // its id is allocated once the record is known to fit into storage
+        let event = new_event(payload, caller());
+        check_size(&event)?;
+        insert_new_event(event)
+    }
+
+
+    // Helper function building an event owned by `owner` from a validated payload; it gets
+    // its id from insert_new_event
+    fn new_event(payload: EventPayload, owner: Principal) -> Event {
+        Event {
+            id: 0,
             event_description: payload.event_description,
-            owner: caller().to_string(),
+            owner,
             event_title: payload.event_title,
             event_location : payload.event_location,
             event_card_imgurl : payload.event_card_imgurl,
-            attendees : Vec::new(),
+            attendee_count : 0,
+            capacity: payload.capacity,
+            starts_at: Some(payload.starts_at),
+            ends_at: Some(payload.ends_at),
+            time_zone: Some(payload.time_zone),
+            recurrence: payload.recurrence,
+            pending_transfer: None,
+            previous_owners: None,
+            hidden_at: None,
+            deleted_at: None,
             created_at: time(),
             updated_at: None,
-        };
+            version: 1,
+        }
+    }
+
+
+    // Helper function allocating an id for an event built by new_event and storing it
+    fn insert_new_event(mut event: Event) -> Result<Event, Error> {
+        // Increment the unique identifier for the new event
+        event.id = ID_COUNTER
+            .with(|counter| {
+                let current_value = *counter.borrow().get();
+                counter.borrow_mut().set(current_value + 1)
+            })
+            .map_err(|_| Error::Internal {
+                msg: "cannot increment id counter".to_string(),
+            })?;
 
         // Insert the newly created event into the storage
         do_insert(&event);
+        audit::record(event.id, audit::AuditOperation::Create, None, Some(&event));
 
-        // Return the newly created event as an Option
-        Some(event)
+        // Return the newly created event
+        Ok(event)
     }
 
 
-    // Update function to modify the details of an existing event
-    #[ic_cdk::update]
-    fn update_event(id: u64, payload: EventPayload) -> Result<Event, Error> {
-    
-    // Check if the caller is the owner of the event; if not, return an authorization error
-    if !_check_if_owner(&_get_event(&id).unwrap().clone()){
-        return Err(Error::NotAuthorized {
-            msg: format!(
-                "You're not the owner of the event with id={}",
-                id
-            ),
-            caller: caller()
+    // Update function to modify the details of an existing event, replacing every field
+    #[ic_cdk::update(guard = "reject_anonymous")]
+    fn update_event(id: u64, expected_version: u64, payload: EventPayload) -> Result<Event, Error> {
+        // Resolve the event and check that the caller may edit it
+        let event = guard::authorize(id, guard::Action::Update)?;
+        guard::check_version(&event, expected_version)?;
+        apply_payload(event, payload, true, audit::AuditOperation::Update)
+    }
+
+
+    // Update function to modify only the fields of an existing event that the patch sets
+    #[ic_cdk::update(guard = "reject_anonymous")]
+    fn patch_event(id: u64, expected_version: u64, patch: EventPatch) -> Result<Event, Error> {
+        let event = guard::authorize(id, guard::Action::Update)?;
+        guard::check_version(&event, expected_version)?;
+        // Events stored before schedules existed keep having none unless the patch sets one
+        let scheduled = patch.sets_schedule() || event.starts_at.is_some();
+        let payload = patch.apply_to(&event);
+        apply_payload(event, payload, scheduled, audit::AuditOperation::Update)
+    }
+
+
+    // Update function letting the owner set the editable fields of an event back to those
+    // of a stored revision; the state it replaces becomes a revision in turn
+    #[ic_cdk::update(guard = "reject_anonymous")]
+    fn rollback_event(id: u64, expected_version: u64, revision: u64) -> Result<Event, Error> {
+        let event = guard::authorize(id, guard::Action::RollBack)?;
+        guard::check_version(&event, expected_version)?;
+        let old = revisions::get(id, revision)?.event;
+        // A revision taken before the event had a schedule rolls back to having none
+        let scheduled = old.starts_at.is_some();
+        let payload = EventPayload {
+            event_description: old.event_description,
+            event_title: old.event_title,
+            event_location: old.event_location,
+            event_card_imgurl: old.event_card_imgurl,
+            starts_at: old.starts_at.unwrap_or_default(),
+            ends_at: old.ends_at.unwrap_or_default(),
+            time_zone: old.time_zone.unwrap_or_default(),
+            recurrence: old.recurrence,
+            capacity: old.capacity,
+        };
+        apply_payload(event, payload, scheduled, audit::AuditOperation::RollBack { revision })
+    }
+
+
+    // Helper function validating a payload and writing it over an existing event, keeping the
+    // replaced state as a revision; without `scheduled` the schedule fields of the payload are
+    // ignored and the event is left without a schedule
+    fn apply_payload(mut event: Event, mut payload: EventPayload, scheduled: bool, operation: audit::AuditOperation) -> Result<Event, Error> {
+        validation::validate(&mut payload)?;
+        if scheduled {
+            validate_schedule(&payload, false)?;
+        } else {
+            validate_capacity(payload.capacity)?;
+        }
+        let before = event.clone();
+
+        // Update event details with the provided payload
+        event.event_description = payload.event_description;
+        event.event_title = payload.event_title;
+        event.event_location  = payload.event_location;
+        event.event_card_imgurl  = payload.event_card_imgurl;
+        event.starts_at = scheduled.then_some(payload.starts_at);
+        event.ends_at = scheduled.then_some(payload.ends_at);
+        event.time_zone = scheduled.then_some(payload.time_zone);
+        event.recurrence = payload.recurrence.filter(|_| scheduled);
+        event.capacity = payload.capacity;
+        event.updated_at = Some(time());
+        event.version += 1;
+        check_size(&event)?;
+
+        // A raised (or removed) capacity frees seats for the waitlist
+        event.attendee_count += attendance::promote_event(&event, time());
+
+        // Insert the modified event back into storage
+        do_insert(&event);
+        revisions::store(&before, caller(), time());
+        audit::record(event.id, operation, Some(&before), Some(&event));
+        Ok(event)
+    }
+
+
+    // Update function to add the caller as an attendee of an event, or of one occurrence
+    // (identified by its start time) of a recurring event; once the event is full the
+    // caller joins its waitlist instead
+    #[ic_cdk::update(guard = "reject_anonymous")]
+    fn attend_event(id: u64, occurrence: Option<u64>) -> Result<AttendResponse, Error> {
+        let mut event = guard::authorize(id, guard::Action::Attend)?;
+        let slot = attendance::resolve_slot(&event, occurrence)?;
+        check_open(&event, slot)?;
+        check_not_declined(&event, slot)?;
+
+        // Check if that caller is already in the attendees list or on the waitlist
+        if attendance::is_attending(slot, caller()) {
+            return Err(Error::AlreadyExists {
+                msg: "You are already an attendee".to_string(),
+            });
+        }
+        if let Some(position) = attendance::waitlist_position(slot, caller()) {
+            return Err(Error::AlreadyExists {
+                msg: format!("You are already on the waitlist at position {}", position),
+            });
+        }
+
+        // A full event puts the caller on its waitlist, as long as that has room
+        if attendance::is_full(slot, event.capacity) {
+            if !attendance::has_waitlist_room(slot) {
+                return Err(Error::CapacityReached {
+                    msg: format!("The event with id={} and its waitlist are full", id),
+                    capacity: event.capacity.unwrap_or_default(),
+                });
+            }
+            let position = attendance::join_waitlist(slot, caller(), time());
+            let operation = audit::AuditOperation::Attend { occurrence, waitlist_position: Some(position) };
+            audit::record(id, operation, Some(&event), Some(&event));
+            return Ok(AttendResponse {
+                event,
+                waitlist_position: Some(position),
+            });
+        }
+
+        let before = event.clone();
+        attendance::add(slot, caller(), time());
+        event.attendee_count += 1;
+
+        do_insert(&event);
+        let operation = audit::AuditOperation::Attend { occurrence, waitlist_position: None };
+        audit::record(id, operation, Some(&before), Some(&event));
+        // Return the modified event on success
+        Ok(AttendResponse {
+            event,
+            waitlist_position: None,
         })
     }
 
-        // Attempt to retrieve the event from storage based on its unique identifier
-        match STORAGE.with(|service| service.borrow().get(&id)) {
-           
-            Some(mut event) => {
-
-                // Update event details with the provided payload
-                event.event_description = payload.event_description;
-                event.event_title = payload.event_title;
-                event.event_location  = payload.event_location;
-                event.event_card_imgurl  = payload.event_card_imgurl;
-                event.updated_at = Some(time());
-                
-                // Insert the modified event back into storage
-                do_insert(&event);
-                Ok(event)
+
+    // Helper function rejecting attendance changes once the event (or occurrence) has ended
+    fn check_open(event: &Event, slot: attendance::Slot) -> Result<(), Error> {
+        if attendance::slot_ends_at(event, slot).is_some_and(|ends_at| ends_at <= time()) {
+            return Err(Error::EventClosed {
+                msg: format!("The event with id={} has already ended", event.id),
+            });
+        }
+        Ok(())
+    }
+
+    // Helper function rejecting callers whose RSVP the host declined
+    fn check_not_declined(event: &Event, slot: attendance::Slot) -> Result<(), Error> {
+        if attendance::rsvp(slot, caller()).is_some_and(|rsvp| rsvp.status == attendance::RsvpStatus::DeclinedByHost) {
+            return Err(Error::NotAuthorized {
+                msg: format!("The host of the event with id={} declined your RSVP", event.id),
+                caller: caller(),
+            });
+        }
+        Ok(())
+    }
+
+
+    // Update function recording the caller's RSVP for an event (or one occurrence); answering
+    // `Going` behaves like attend_event, any other answer gives up a held seat or waitlist entry
+    #[ic_cdk::update(guard = "reject_anonymous")]
+    fn rsvp_event(id: u64, occurrence: Option<u64>, status: attendance::RsvpStatus) -> Result<attendance::Rsvp, Error> {
+        let slot = match status {
+            attendance::RsvpStatus::Going => {
+                let response = attend_event(id, occurrence)?;
+                let slot = attendance::resolve_slot(&response.event, occurrence)?;
+                return attendance::rsvp(slot, caller()).ok_or_else(|| Error::Internal {
+                    msg: format!("RSVP for the event with id={} was not recorded", id),
+                });
+            }
+            attendance::RsvpStatus::Maybe | attendance::RsvpStatus::NotGoing => {
+                let mut event = guard::authorize(id, guard::Action::Attend)?;
+                let slot = attendance::resolve_slot(&event, occurrence)?;
+                check_open(&event, slot)?;
+                check_not_declined(&event, slot)?;
+                let before = event.clone();
+                if attendance::release(&mut event, slot, caller(), time()) {
+                    do_insert(&event);
+                    let operation = audit::AuditOperation::CancelAttendance { occurrence };
+                    audit::record(id, operation, Some(&before), Some(&event));
+                }
+                slot
             }
+            attendance::RsvpStatus::Waitlisted | attendance::RsvpStatus::DeclinedByHost => {
+                return Err(Error::invalid_field("status", "only Going, Maybe and NotGoing can be chosen"));
+            }
+        };
+        Ok(attendance::set_rsvp(slot, caller(), status, time()))
+    }
 
-            // If the event is not found, return a NotFound error
-            None => Err(Error::NotFound {
-                msg: format!(
-                    "couldn't update an event with id={}. event not found",
-                    id
-                ),
-            }),
+
+    // Update function cancelling the caller's attendance (or waitlist entry) for an event or
+    // one occurrence; the first waitlisted principal takes the freed seat
+    #[ic_cdk::update(guard = "reject_anonymous")]
+    fn cancel_attendance(id: u64, occurrence: Option<u64>) -> Result<Event, Error> {
+        let mut event = guard::authorize(id, guard::Action::Attend)?;
+        let slot = attendance::resolve_slot(&event, occurrence)?;
+        check_open(&event, slot)?;
+        let before = event.clone();
+        if !attendance::release(&mut event, slot, caller(), time()) {
+            return Err(Error::NotFound {
+                msg: format!("You are not attending the event with id={}", id),
+            });
         }
+        attendance::set_rsvp(slot, caller(), attendance::RsvpStatus::NotGoing, time());
+        do_insert(&event);
+        audit::record(id, audit::AuditOperation::CancelAttendance { occurrence }, Some(&before), Some(&event));
+        Ok(event)
     }
 
 
-    // Update function to add an attendee to a specific event
-    #[ic_cdk::update]
-    fn attend_event(id: u64) -> Result<Event, Error> {
-    
-    // Attempt to retrieve the event from storage based on its unique identifier
-    match STORAGE.with(|service| service.borrow().get(&id)) {
-        Some(mut event) => {
-            // Get the caller's identity as an attendee
-            let attendee = caller().to_string();
-            
-            // Retrieve the current list of attendees for the event
-            let mut attendees: Vec<String> = event.attendees;
-
-            // Check if that caller is already in the attendees list
-            if attendees.contains(&attendee) {
-                // Return an error message
-                Err(Error::NotFound {
-                    msg: format!("You are already an attendee"),
-                })
-            } else {
-                attendees.push(attendee);
-                event.attendees = attendees;
+    // Update function letting the owner, a co-host or a moderator decline a principal's RSVP,
+    // freeing their seat
+    #[ic_cdk::update(guard = "reject_anonymous")]
+    fn decline_attendee(id: u64, occurrence: Option<u64>, principal: Principal) -> Result<Event, Error> {
+        let mut event = guard::authorize(id, guard::Action::ManageAttendees)?;
+        let slot = attendance::resolve_slot(&event, occurrence)?;
+        let before = event.clone();
+        if attendance::release(&mut event, slot, principal, time()) {
+            do_insert(&event);
+        }
+        attendance::set_rsvp(slot, principal, attendance::RsvpStatus::DeclinedByHost, time());
+        let operation = audit::AuditOperation::DeclineAttendee { occurrence, principal };
+        audit::record(id, operation, Some(&before), Some(&event));
+        Ok(event)
+    }
+
+
+    // Update function letting event staff check in an attendee at the door; returns the check-in time
+    #[ic_cdk::update(guard = "reject_anonymous")]
+    fn check_in_attendee(id: u64, occurrence: Option<u64>, principal: Principal) -> Result<u64, Error> {
+        let event = guard::authorize(id, guard::Action::CheckIn)?;
+        let slot = attendance::resolve_slot(&event, occurrence)?;
+        attendance::check_in(slot, principal, time())
+    }
+
+
+    // Update function granting a principal a role on an event, replacing the role they held;
+    // callers may only grant roles below their own
+    #[ic_cdk::update(guard = "reject_anonymous")]
+    fn grant_role(id: u64, principal: Principal, role: roles::Role) -> Result<(), Error> {
+        let event = guard::authorize(id, guard::Action::ManageRoles)?;
+        guard::authorize_role_change(&event, role)?;
+        if let Some(current) = roles::role_of(&event, principal) {
+            guard::authorize_role_change(&event, current)?;
+        }
+        roles::grant(id, principal, role, time());
+        Ok(())
+    }
 
-                do_insert(&event);
-                // Return the modified event on success
-                Ok(event)
+
+    // Update function taking away the role a principal holds on an event
+    #[ic_cdk::update(guard = "reject_anonymous")]
+    fn revoke_role(id: u64, principal: Principal) -> Result<(), Error> {
+        let event = guard::authorize(id, guard::Action::ManageRoles)?;
+        let role = roles::role_of(&event, principal).ok_or_else(|| Error::NotFound {
+            msg: format!("{} holds no role on the event with id={}", principal, id),
+        })?;
+        guard::authorize_role_change(&event, role)?;
+        roles::revoke(id, principal);
+        Ok(())
+    }
+
+
+    // Update function removing the caller from the waitlist of an event (or of one occurrence)
+    #[ic_cdk::update(guard = "reject_anonymous")]
+    fn leave_waitlist(id: u64, occurrence: Option<u64>) -> Result<(), Error> {
+        let event = guard::authorize(id, guard::Action::Attend)?;
+        let slot = attendance::resolve_slot(&event, occurrence)?;
+        if !attendance::leave_waitlist(slot, caller()) {
+            return Err(Error::NotFound {
+                msg: format!("You are not on the waitlist of the event with id={}", id),
+            });
+        }
+        attendance::set_rsvp(slot, caller(), attendance::RsvpStatus::NotGoing, time());
+        audit::record(id, audit::AuditOperation::CancelAttendance { occurrence }, Some(&event), Some(&event));
+        Ok(())
+    }
+
+
+    // Update function letting the owner propose handing an event over to `new_owner`, who has
+    // `expires_in` nanoseconds (7 days by default) to accept; replaces any pending proposal
+    #[ic_cdk::update(guard = "reject_anonymous")]
+    fn propose_ownership_transfer(id: u64, new_owner: Principal, expires_in: Option<u64>) -> Result<Event, Error> {
+        let mut event = guard::authorize(id, guard::Action::TransferOwnership)?;
+        let before = event.clone();
+        ownership::propose(&mut event, new_owner, expires_in, time())?;
+        event.version += 1;
+        check_stored_size(&event)?;
+        do_insert(&event);
+        let operation = audit::AuditOperation::ProposeOwnershipTransfer { to: new_owner };
+        audit::record(id, operation, Some(&before), Some(&event));
+        Ok(event)
+    }
+
+
+    // Update function letting the proposed owner accept a pending ownership transfer
+    #[ic_cdk::update(guard = "reject_anonymous")]
+    fn accept_ownership_transfer(id: u64) -> Result<Event, Error> {
+        let mut event = guard::authorize_transfer_recipient(id)?;
+        let before = event.clone();
+        ownership::accept(&mut event, caller(), time())?;
+        event.updated_at = Some(time());
+        event.version += 1;
+        check_stored_size(&event)?;
+        do_insert(&event);
+        audit::record(id, audit::AuditOperation::TransferOwnership, Some(&before), Some(&event));
+        Ok(event)
+    }
+
+
+    // Update function letting the owner withdraw a pending ownership transfer
+    #[ic_cdk::update(guard = "reject_anonymous")]
+    fn cancel_ownership_transfer(id: u64) -> Result<Event, Error> {
+        let mut event = guard::authorize(id, guard::Action::TransferOwnership)?;
+        let before = event.clone();
+        ownership::cancel(&mut event)?;
+        event.version += 1;
+        do_insert(&event);
+        let operation = audit::AuditOperation::CancelOwnershipTransfer;
+        audit::record(id, operation, Some(&before), Some(&event));
+        Ok(event)
+    }
+
+
+    // Update function to delete a specific event by its unique identifier; the event goes to
+    // the trash, where its owner can restore it until it is purged
+    #[ic_cdk::update(guard = "reject_anonymous")]
+    fn delete_event(id: u64, expected_version: u64) -> Result<Event, Error> {
+        // Resolve the event and check that the caller may delete it
+        let event = guard::authorize(id, guard::Action::Delete)?;
+        guard::check_version(&event, expected_version)?;
+
+        // Move the event to the trash, dropping its index entries
+        let event = do_trash(event)?;
+        audit::record(id, audit::AuditOperation::Delete, Some(&event), None);
+        Ok(event)
+    }
+
+
+    // Update function letting the owner bring an event back from the trash
+    #[ic_cdk::update(guard = "reject_anonymous")]
+    fn restore_event(id: u64) -> Result<Event, Error> {
+        let mut event = trash::take(id, caller())?;
+        event.deleted_at = None;
+        event.updated_at = Some(time());
+        event.version += 1;
+        do_insert(&event);
+        audit::record(id, audit::AuditOperation::Restore, None, Some(&event));
+        Ok(event)
+    }
+
+
+    // Query function listing the caller's trashed events by id, starting after `cursor`
+    #[ic_cdk::query]
+    fn list_trash(cursor: Option<u64>, limit: u32) -> trash::TrashPage {
+        trash::page(caller(), cursor, limit)
+    }
+
+
+    // Update function letting a canister admin hide an event from everyone but its staff
+    #[ic_cdk::update(guard = "reject_anonymous")]
+    fn hide_event(id: u64, reason: Option<String>) -> Result<Event, Error> {
+        let admin = guard::authorize_admin()?;
+        admin::validate_reason(&reason)?;
+        let mut event = _get_event(&id).ok_or_else(|| Error::event_not_found(id))?;
+        if event.hidden_at.is_some() {
+            return Err(Error::AlreadyExists {
+                msg: format!("The event with id={} is already hidden", id),
+            });
+        }
+        event.hidden_at = Some(time());
+        check_stored_size(&event)?;
+        do_insert(&event);
+        admin::record(admin, admin::ModerationKind::HideEvent { id }, reason, time());
+        Ok(event)
+    }
+
+
+    // Update function letting a canister admin make a hidden event visible again
+    #[ic_cdk::update(guard = "reject_anonymous")]
+    fn unhide_event(id: u64, reason: Option<String>) -> Result<Event, Error> {
+        let admin = guard::authorize_admin()?;
+        admin::validate_reason(&reason)?;
+        let mut event = _get_event(&id).ok_or_else(|| Error::event_not_found(id))?;
+        if event.hidden_at.take().is_none() {
+            return Err(Error::NotFound {
+                msg: format!("The event with id={} is not hidden", id),
+            });
+        }
+        do_insert(&event);
+        admin::record(admin, admin::ModerationKind::UnhideEvent { id }, reason, time());
+        Ok(event)
+    }
+
+
+    // Update function letting a canister admin delete any event, including one in the trash
+    #[ic_cdk::update(guard = "reject_anonymous")]
+    fn force_delete_event(id: u64, reason: Option<String>) -> Result<Event, Error> {
+        let admin = guard::authorize_admin()?;
+        admin::validate_reason(&reason)?;
+        let event = match do_remove(id) {
+            Some(event) => event,
+            None => {
+                let event = trash::purge(id).ok_or_else(|| Error::event_not_found(id))?;
+                trash::schedule_purge();
+                event
             }
+        };
+        admin::record(admin, admin::ModerationKind::ForceDeleteEvent { id }, reason, time());
+        audit::record(id, audit::AuditOperation::ForceDelete, Some(&event), None);
+        Ok(event)
+    }
+
+
+    // Update function letting a canister admin ban a principal from creating events
+    #[ic_cdk::update(guard = "reject_anonymous")]
+    fn ban_principal(principal: Principal, reason: Option<String>) -> Result<(), Error> {
+        let admin = guard::authorize_admin()?;
+        admin::validate_reason(&reason)?;
+        if admin::is_admin(principal) {
+            return Err(Error::invalid_field("principal", "canister admins cannot be banned"));
+        }
+        if !admin::ban(principal, time()) {
+            return Err(Error::AlreadyExists {
+                msg: format!("{} is already banned", principal),
+            });
+        }
+        admin::record(admin, admin::ModerationKind::BanPrincipal { principal }, reason, time());
+        Ok(())
+    }
+
+
+    // Update function letting a canister admin lift a ban
+    #[ic_cdk::update(guard = "reject_anonymous")]
+    fn unban_principal(principal: Principal, reason: Option<String>) -> Result<(), Error> {
+        let admin = guard::authorize_admin()?;
+        admin::validate_reason(&reason)?;
+        if !admin::unban(principal) {
+            return Err(Error::NotFound {
+                msg: format!("{} is not banned", principal),
+            });
+        }
+        admin::record(admin, admin::ModerationKind::UnbanPrincipal { principal }, reason, time());
+        Ok(())
+    }
+
+
+    // Update function letting a canister admin add another admin
+    #[ic_cdk::update(guard = "reject_anonymous")]
+    fn add_admin(principal: Principal) -> Result<(), Error> {
+        let admin = guard::authorize_admin()?;
+        if principal == Principal::anonymous() {
+            return Err(Error::invalid_field("principal", "must not be the anonymous principal"));
+        }
+        if !admin::add_admin(principal, time()) {
+            return Err(Error::AlreadyExists {
+                msg: format!("{} is already an admin", principal),
+            });
         }
+        admin::record(admin, admin::ModerationKind::AddAdmin { principal }, None, time());
+        Ok(())
+    }
+
 
-        // If the event is not found, return a NotFound error
-        None => Err(Error::NotFound {
-            msg: format!("Couldn't update an event with id={}. Event not found", id),
-        }),
+    // Update function letting a canister admin remove a listed admin; controllers stay admins
+    #[ic_cdk::update(guard = "reject_anonymous")]
+    fn remove_admin(principal: Principal) -> Result<(), Error> {
+        let admin = guard::authorize_admin()?;
+        if !admin::remove_admin(principal) {
+            return Err(Error::NotFound {
+                msg: format!("{} is not a listed admin", principal),
+            });
+        }
+        admin::record(admin, admin::ModerationKind::RemoveAdmin { principal }, None, time());
+        Ok(())
     }
-}
 
 
+    // Update function letting a canister admin change the limits event payloads are validated against
+    #[ic_cdk::update(guard = "reject_anonymous")]
+    fn set_validation_limits(limits: validation::ValidationLimits) -> Result<(), Error> {
+        guard::authorize_admin()?;
+        validation::set_limits(limits)
+    }
 
-    // Update function to delete a specific event by its unique identifier
-    #[ic_cdk::update]
-    fn delete_event(id: u64) -> Result<Event, Error> {
-    
-    // Check if the caller is the owner of the event; if not, return an authorization error
-    if !_check_if_owner(&_get_event(&id).unwrap().clone()){
-        return Err(Error::NotAuthorized {
-            msg: format!(
-                "You're not the owner of the event with id={}",
-                id
-            ),
-            caller: caller()
-        })
+
+    // Query function returning the limits event payloads are validated against
+    #[ic_cdk::query]
+    fn get_validation_limits() -> validation::ValidationLimits {
+        validation::limits()
     }
 
-    // Attempt to remove the event from storage based on its unique identifier
-    match STORAGE.with(|service| service.borrow_mut().remove(&id)) {
-        
-        // If the event is found and removed, return it as a Result::Ok
-        Some(event) => Ok(event),
 
-        // If the event is not found, return a Result::Err with a NotFound error
-        None => Err(Error::NotFound {
-            msg: format!(
-                "couldn't delete an event with id={}. To-do not found.",
-                id
-            ),
-            }),
+    // Query function listing the stored revisions of an event newest first, each with the
+    // changes made by the edit that replaced it
+    #[ic_cdk::query]
+    fn get_event_history(id: u64) -> Result<Vec<revisions::RevisionSummary>, Error> {
+        let event = guard::authorize(id, guard::Action::ViewHistory)?;
+        Ok(revisions::history(&event))
+    }
+
+
+    // Query function returning a stored revision of an event in full
+    #[ic_cdk::query]
+    fn get_event_revision(id: u64, revision: u64) -> Result<revisions::Revision, Error> {
+        guard::authorize(id, guard::Action::ViewHistory)?;
+        revisions::get(id, revision)
+    }
+
+
+    // Query function listing the audit log of an event oldest first, starting after sequence
+    // number `cursor`; open to the event's owner, co-hosts and moderators and to canister admins
+    #[ic_cdk::query]
+    fn get_event_audit_log(id: u64, cursor: Option<u64>, limit: u32) -> Result<audit::AuditPage, Error> {
+        if !admin::is_admin(caller()) {
+            guard::authorize(id, guard::Action::ViewHistory)?;
+        }
+        Ok(audit::for_event(id, cursor, limit))
+    }
+
+
+    // Query function listing the audit entries of calls made by `principal` oldest first,
+    // starting after sequence number `cursor`; open to that principal and to canister admins
+    #[ic_cdk::query]
+    fn get_principal_audit_log(principal: Principal, cursor: Option<u64>, limit: u32) -> Result<audit::AuditPage, Error> {
+        if principal != caller() {
+            guard::authorize_admin()?;
         }
+        Ok(audit::for_principal(principal, cursor, limit))
+    }
+
+
+    // Update function letting a canister admin create many events at once from a JSON array or
+    // a CSV document; with `dry_run`, or when any row is invalid, nothing is created and the
+    // report lists the failing rows
+    #[ic_cdk::update(guard = "reject_anonymous")]
+    fn import_events(format: bulk::BulkFormat, data: String, dry_run: bool) -> Result<bulk::ImportReport, Error> {
+        let admin = guard::authorize_admin()?;
+        bulk::import(format, &data, dry_run, admin)
     }
 
 
-    // Enum representing various error scenarios that can occur during event operations
-    #[derive(candid::CandidType, Deserialize, Serialize)]
-    enum Error {
-        // Indicates that the requested event was not found
-        NotFound { msg: String },
+    // Query function letting a canister admin export stored events by id, starting after `cursor`
+    #[ic_cdk::query]
+    fn export_events(format: bulk::BulkFormat, cursor: Option<u64>, limit: u32) -> Result<bulk::ExportPage, Error> {
+        guard::authorize_admin()?;
+        bulk::export(format, cursor, limit)
+    }
+
+
+    // Query function letting a canister admin export the seats of a stored event, starting
+    // after `cursor`
+    #[ic_cdk::query]
+    fn export_event_seats(format: bulk::BulkFormat, id: u64, cursor: Option<bulk::SeatCursor>, limit: u32) -> Result<bulk::SeatExportPage, Error> {
+        guard::authorize_admin()?;
+        bulk::export_seats(format, id, cursor, limit)
+    }
+
+
+    // Query function listing the canister admins besides the controllers
+    #[ic_cdk::query]
+    fn list_admins() -> Result<Vec<Principal>, Error> {
+        guard::authorize_admin()?;
+        Ok(admin::admins())
+    }
+
 
-        // Indicates an authorization error when the caller is not the owner of the event
-        NotAuthorized {msg: String , caller: Principal},
+    // Query function listing moderation actions oldest first, starting after sequence number `cursor`
+    #[ic_cdk::query]
+    fn list_moderation_actions(cursor: Option<u64>, limit: u32) -> Result<admin::ModerationPage, Error> {
+        guard::authorize_admin()?;
+        Ok(admin::page(cursor, limit))
     }
 
 
-     // Helper method to insert an event.
+     // Helper function rejecting event details that would leave no headroom below the stable
+     // map entry size; used wherever the details of an event are set
+     fn check_size(event: &Event) -> Result<(), Error> {
+        check_size_within(event, Event::MAX_SIZE - EVENT_SIZE_HEADROOM)
+     }
+
+     // Helper function rejecting events that would not fit a stable map entry; used before
+     // writes that only grow an event by fields covered by the headroom
+     fn check_stored_size(event: &Event) -> Result<(), Error> {
+        check_size_within(event, Event::MAX_SIZE)
+     }
+
+     fn check_size_within(event: &Event, limit: u32) -> Result<(), Error> {
+        let size = event.to_bytes().len();
+        if size > limit as usize {
+            return Err(Error::InvalidInput {
+                msg: format!("event is {} bytes, above the {} byte limit", size, limit),
+                fields: Vec::new(),
+            });
+        }
+        Ok(())
+     }
+
+     // Helper method to insert an event. Writes that grow an event check its size first, so
+     // an oversized record here is a bug and traps to roll the call back
      fn do_insert(event: &Event) {
-        STORAGE.with(|service| service.borrow_mut().insert(event.id, event.clone()));
+        // The stable map asserts that values fit `MAX_SIZE`; check first to trap with the event id
+        let size = event.to_bytes().len();
+        if size > Event::MAX_SIZE as usize {
+            ic_cdk::trap(&format!(
+                "event with id={} is {} bytes, above the {} byte limit",
+                event.id, size, Event::MAX_SIZE
+            ));
+        }
+        let previous = STORAGE.with(|service| service.borrow_mut().insert(event.id, event.clone()));
+        listing::reindex(previous.as_ref(), Some(event));
+        search::reindex(previous.as_ref(), Some(event));
+        certification::update(event.id, Some(event));
+        certification::publish();
+    }
+
+    // Helper method to remove an event together with its attendees and index entries
+    fn do_remove(id: u64) -> Option<Event> {
+        let event = STORAGE.with(|service| service.borrow_mut().remove(&id))?;
+        listing::reindex(Some(&event), None);
+        search::reindex(Some(&event), None);
+        certification::update(id, None);
+        certification::publish();
+        attendance::remove_event(id);
+        roles::remove_event(id);
+        revisions::remove_event(id);
+        Some(event)
+    }
+
+    // Helper method to move a stored event to the trash, keeping its attendees and roles for a
+    // restore; the trashed record is size-checked before anything is removed
+    fn do_trash(mut event: Event) -> Result<Event, Error> {
+        event.deleted_at = Some(time());
+        check_stored_size(&event)?;
+        let id = event.id;
+        let stored = STORAGE.with(|service| service.borrow_mut().remove(&id));
+        listing::reindex(stored.as_ref(), None);
+        search::reindex(stored.as_ref(), None);
+        certification::update(id, None);
+        certification::publish();
+        trash::insert(event.clone());
+        Ok(event)
     }
 
     // Helper method to retrieve an event by it's id 
     fn _get_event(id: &u64) -> Option<Event> {
         STORAGE.with(|s| s.borrow().get(id))
     }
-    
-    // Helper function to check whether the caller is the owner of the event
-    fn _check_if_owner(event: &Event) -> bool {
-    if event.owner.to_string() != caller().to_string(){
-        false  
-    }else{
-        true
+
+    // Helper method to retrieve an event the caller may see; hidden events are reported as missing
+    fn _get_visible_event(id: u64) -> Result<Event, Error> {
+        _get_event(&id)
+            .filter(|event| guard::is_visible(event, caller()))
+            .ok_or_else(|| Error::event_not_found(id))
     }
-}
 
+    // An unscheduled event owned by the anonymous principal, shared by the unit tests
+    #[cfg(test)]
+    fn test_event(id: u64) -> Event {
+        Event {
+            id,
+            event_description: String::new(),
+            owner: Principal::anonymous(),
+            event_title: String::new(),
+            event_location: String::new(),
+            event_card_imgurl: String::new(),
+            attendee_count: 0,
+            capacity: None,
+            starts_at: None,
+            ends_at: None,
+            time_zone: None,
+            recurrence: None,
+            pending_transfer: None,
+            previous_owners: None,
+            hidden_at: None,
+            deleted_at: None,
+            created_at: 0,
+            updated_at: None,
+            version: 1,
+        }
+    }
 
+    #[cfg(test)]
+    mod tests {
+        use super::*;
 
+        fn payload(time_zone: &str, rule: Option<recurrence::RecurrenceRule>) -> EventPayload {
+            EventPayload {
+                event_description: String::new(),
+                event_title: "Title".to_string(),
+                event_location: String::new(),
+                event_card_imgurl: String::new(),
+                starts_at: 10,
+                ends_at: 20,
+                time_zone: time_zone.to_string(),
+                recurrence: rule,
+                capacity: None,
+            }
+        }
+
+        #[test]
+        fn recurring_events_must_use_utc() {
+            let weekly = recurrence::RecurrenceRule {
+                frequency: recurrence::Frequency::Weekly,
+                interval: None,
+                by_day: Vec::new(),
+                count: Some(4),
+                until: None,
+                exceptions: Vec::new(),
+            };
+            assert!(validate_schedule(&payload("Europe/Berlin", None), false).is_ok());
+            for time_zone in ["UTC", "Etc/UTC", "GMT"] {
+                let payload = payload(time_zone, Some(weekly.clone()));
+                assert!(validate_schedule(&payload, false).is_ok());
+            }
+            let Err(Error::InvalidInput { fields, .. }) =
+                validate_schedule(&payload("Europe/Berlin", Some(weekly)), false)
+            else {
+                panic!("a recurring event in a DST time zone should be rejected");
+            };
+            assert_eq!(fields[0].field, "time_zone");
+        }
+    }
+    
     // need this to generate candid
     ic_cdk::export_candid!();
 
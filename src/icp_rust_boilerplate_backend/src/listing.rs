@@ -0,0 +1,474 @@
+// Cursor-based event listing.
+//
+// Events are read by id straight from `STORAGE`, or by one of their timestamps
+// through the `(timestamp, id)` secondary indexes (`CREATED_INDEX`,
+// `UPDATED_INDEX`, `STARTS_INDEX`, `ENDS_INDEX`). `OWNER_INDEX` lists the
+// events of each owner by id. Every page scans a bounded
+// number of records, so heavily filtered listings may return short (even empty)
+// pages together with a cursor to continue from.
+//
+// The upcoming and ongoing listings classify events by their current or next
+// occurrence, so a series keeps showing up for as long as it has occurrences.
+
+use candid::{CandidType, Principal};
+use ic_cdk::api::time;
+use ic_stable_structures::StableBTreeMap;
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::ops::Bound;
+use std::thread::LocalKey;
+
+use crate::{
+    attendance, recurrence, Event, Memory, StorablePrincipal, CREATED_INDEX, ENDS_INDEX,
+    OWNER_INDEX, STARTS_INDEX, STORAGE, UPDATED_INDEX,
+};
+
+// Page size used when the request does not ask for one
+const DEFAULT_PAGE_SIZE: u32 = 20;
+
+// Largest page size served by list_events
+const MAX_PAGE_SIZE: u32 = 100;
+
+// Most records inspected while filling a single page
+const MAX_SCANNED: usize = 1_000;
+
+type TimeIndex = RefCell<StableBTreeMap<(u64, u64), (), Memory>>;
+
+// Field the listing is ordered by (always ascending, ties broken by id)
+#[derive(CandidType, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Default)]
+pub enum ListOrder {
+    #[default]
+    Id,
+    CreatedAt,
+    UpdatedAt,
+    StartsAt,
+    EndsAt,
+}
+
+// Position to resume a listing from; returned as `next_cursor`
+#[derive(CandidType, Clone, Serialize, Deserialize)]
+pub struct ListCursor {
+    // Sort value of the last returned event (its id when ordering by id)
+    pub sort_value: u64,
+    pub start_after: u64,
+}
+
+// Request accepted by list_events
+#[derive(CandidType, Clone, Serialize, Deserialize, Default)]
+pub struct ListRequest {
+    pub cursor: Option<ListCursor>,
+    pub limit: Option<u32>,
+    pub order_by: Option<ListOrder>,
+    // Only events owned by this principal
+    pub owner: Option<Principal>,
+    // Only events created within [created_from, created_to]
+    pub created_from: Option<u64>,
+    pub created_to: Option<u64>,
+    // Only scheduled events starting within [starts_from, starts_to]
+    pub starts_from: Option<u64>,
+    pub starts_to: Option<u64>,
+    // Only scheduled events ending within [ends_from, ends_to]
+    pub ends_from: Option<u64>,
+    pub ends_to: Option<u64>,
+    // Only events that still have free seats (true) or are full (false); recurring
+    // events are judged by their capacity alone, since seats are per occurrence
+    pub has_capacity: Option<bool>,
+}
+
+// Page of events returned by list_events
+#[derive(CandidType, Serialize, Deserialize)]
+pub struct ListResponse {
+    pub events: Vec<Event>,
+    pub next_cursor: Option<ListCursor>,
+}
+
+// Value an event is sorted by for the given order, if it has one
+fn sort_value(event: &Event, order: ListOrder) -> Option<u64> {
+    match order {
+        ListOrder::Id => Some(event.id),
+        ListOrder::CreatedAt => Some(event.created_at),
+        // Events that were never updated sort by their creation time
+        ListOrder::UpdatedAt => Some(event.updated_at.unwrap_or(event.created_at)),
+        ListOrder::StartsAt => event.starts_at,
+        // Recurring events end with their last occurrence
+        ListOrder::EndsAt => recurrence::schedule_end(event),
+    }
+}
+
+// Secondary index backing a timestamp order
+fn index_for(order: ListOrder) -> Option<&'static LocalKey<TimeIndex>> {
+    match order {
+        ListOrder::Id => None,
+        ListOrder::CreatedAt => Some(&CREATED_INDEX),
+        ListOrder::UpdatedAt => Some(&UPDATED_INDEX),
+        ListOrder::StartsAt => Some(&STARTS_INDEX),
+        ListOrder::EndsAt => Some(&ENDS_INDEX),
+    }
+}
+
+// Keeps the secondary indexes in step with a write to `STORAGE`
+pub fn reindex(old: Option<&Event>, new: Option<&Event>) {
+    reindex_owner(old, new);
+    for order in [
+        ListOrder::CreatedAt,
+        ListOrder::UpdatedAt,
+        ListOrder::StartsAt,
+        ListOrder::EndsAt,
+    ] {
+        let Some(index) = index_for(order) else {
+            continue;
+        };
+        index.with(|index| {
+            let mut index = index.borrow_mut();
+            if let Some(old) = old {
+                if let Some(value) = sort_value(old, order) {
+                    index.remove(&(value, old.id));
+                }
+            }
+            if let Some(new) = new {
+                if let Some(value) = sort_value(new, order) {
+                    index.insert((value, new.id), ());
+                }
+            }
+        });
+    }
+}
+
+// Keeps `OWNER_INDEX` in step with a write to `STORAGE`
+pub fn reindex_owner(old: Option<&Event>, new: Option<&Event>) {
+    OWNER_INDEX.with(|index| {
+        let mut index = index.borrow_mut();
+        if let Some(old) = old {
+            index.remove(&(StorablePrincipal(old.owner), old.id));
+        }
+        if let Some(new) = new {
+            index.insert((StorablePrincipal(new.owner), new.id), ());
+        }
+    });
+}
+
+// Events of `owner` that are not hidden, by id, at most `limit` of them
+pub fn owned_by(owner: Principal, limit: usize) -> Vec<Event> {
+    let key = StorablePrincipal(owner);
+    OWNER_INDEX.with(|index| {
+        index
+            .borrow()
+            .range((key, 0)..=(key, u64::MAX))
+            .filter_map(|((_, id), _)| STORAGE.with(|s| s.borrow().get(&id)))
+            .filter(|event| event.hidden_at.is_none())
+            .take(limit)
+            .collect()
+    })
+}
+
+// Whether an optional timestamp lies within an optional inclusive range;
+// unscheduled events never match a range on their schedule
+fn within(value: Option<u64>, from: Option<u64>, to: Option<u64>) -> bool {
+    if from.is_none() && to.is_none() {
+        return true;
+    }
+    value.is_some_and(|value| {
+        from.is_none_or(|from| value >= from) && to.is_none_or(|to| value <= to)
+    })
+}
+
+// Whether an event can take more attendees
+fn has_seats(event: &Event) -> bool {
+    match event.recurrence {
+        Some(_) => true,
+        None => !attendance::is_full((event.id, attendance::WHOLE_EVENT), event.capacity),
+    }
+}
+
+impl ListRequest {
+    fn matches(&self, event: &Event) -> bool {
+        if event.hidden_at.is_some() {
+            return false;
+        }
+        if let Some(owner) = self.owner {
+            if event.owner != owner {
+                return false;
+            }
+        }
+        if let Some(has_capacity) = self.has_capacity {
+            if has_seats(event) != has_capacity {
+                return false;
+            }
+        }
+        within(Some(event.created_at), self.created_from, self.created_to)
+            && within(event.starts_at, self.starts_from, self.starts_to)
+            // Only work out when a series ends if the request filters on it
+            && (self.ends_from.is_none() && self.ends_to.is_none()
+                || within(recurrence::schedule_end(event), self.ends_from, self.ends_to))
+    }
+
+    // Range filter on the field the listing is ordered by, used to seek and stop early
+    fn order_bounds(&self, order: ListOrder) -> (Option<u64>, Option<u64>) {
+        match order {
+            ListOrder::CreatedAt => (self.created_from, self.created_to),
+            ListOrder::StartsAt => (self.starts_from, self.starts_to),
+            ListOrder::EndsAt => (self.ends_from, self.ends_to),
+            ListOrder::Id | ListOrder::UpdatedAt => (None, None),
+        }
+    }
+}
+
+// Whether the next occurrence of an event starts after `now`, with none in progress
+fn is_upcoming(event: &Event, now: u64) -> bool {
+    recurrence::current_or_next(event, now).is_some_and(|start| start > now)
+}
+
+// Whether an occurrence of an event is in progress at `now`
+fn is_ongoing(event: &Event, now: u64) -> bool {
+    recurrence::current_or_next(event, now).is_some_and(|start| start <= now)
+}
+
+// Events whose next occurrence is yet to start, by the end of their schedule
+pub fn upcoming(cursor: Option<ListCursor>, limit: Option<u32>) -> ListResponse {
+    let now = time();
+    let request = ListRequest {
+        cursor,
+        limit,
+        order_by: Some(ListOrder::EndsAt),
+        ends_from: Some(now.saturating_add(1)),
+        ..Default::default()
+    };
+    list_where(&request, &|event| is_upcoming(event, now))
+}
+
+// Events with an occurrence in progress, by the end of their schedule
+pub fn ongoing(cursor: Option<ListCursor>, limit: Option<u32>) -> ListResponse {
+    let now = time();
+    let request = ListRequest {
+        cursor,
+        limit,
+        order_by: Some(ListOrder::EndsAt),
+        starts_to: Some(now),
+        ends_from: Some(now.saturating_add(1)),
+        ..Default::default()
+    };
+    list_where(&request, &|event| is_ongoing(event, now))
+}
+
+// Events that have already ended, oldest first
+pub fn past(cursor: Option<ListCursor>, limit: Option<u32>) -> ListResponse {
+    list(&ListRequest {
+        cursor,
+        limit,
+        order_by: Some(ListOrder::EndsAt),
+        ends_to: Some(time()),
+        ..Default::default()
+    })
+}
+
+// Returns the next page of events matching the request
+pub fn list(request: &ListRequest) -> ListResponse {
+    list_where(request, &|_| true)
+}
+
+// Returns the next page of events matching both the request and `filter`
+fn list_where(request: &ListRequest, filter: &dyn Fn(&Event) -> bool) -> ListResponse {
+    let order = request.order_by.unwrap_or_default();
+    let limit = request
+        .limit
+        .unwrap_or(DEFAULT_PAGE_SIZE)
+        .clamp(1, MAX_PAGE_SIZE) as usize;
+    let (lower, upper) = request.order_bounds(order);
+
+    let mut page = Page {
+        request,
+        filter,
+        upper,
+        limit,
+        events: Vec::new(),
+        last_seen: None,
+        scanned: 0,
+        finished: true,
+    };
+
+    match index_for(order) {
+        None => {
+            let start = match &request.cursor {
+                Some(cursor) => Bound::Excluded(cursor.start_after),
+                None => Bound::Unbounded,
+            };
+            STORAGE.with(|s| {
+                for (id, event) in s.borrow().range((start, Bound::Unbounded)) {
+                    if !page.visit(id, id, || Some(event)) {
+                        break;
+                    }
+                }
+            });
+        }
+        Some(index) => {
+            let start = match (&request.cursor, lower) {
+                (Some(cursor), _) => Bound::Excluded((cursor.sort_value, cursor.start_after)),
+                // A range filter on the ordered field seeks straight into the index
+                (None, Some(from)) => Bound::Included((from, 0)),
+                (None, None) => Bound::Unbounded,
+            };
+            index.with(|index| {
+                for ((value, id), _) in index.borrow().range((start, Bound::Unbounded)) {
+                    if !page.visit(value, id, || STORAGE.with(|s| s.borrow().get(&id))) {
+                        break;
+                    }
+                }
+            });
+        }
+    }
+
+    let next_cursor = if page.finished { None } else { page.last_seen };
+    ListResponse {
+        events: page.events,
+        next_cursor,
+    }
+}
+
+// Page being filled by `list`
+struct Page<'a> {
+    request: &'a ListRequest,
+    filter: &'a dyn Fn(&Event) -> bool,
+    // Upper bound of the range filter on the ordered field
+    upper: Option<u64>,
+    limit: usize,
+    events: Vec<Event>,
+    last_seen: Option<ListCursor>,
+    scanned: usize,
+    // Whether the scan reached the end of the matching range
+    finished: bool,
+}
+
+impl Page<'_> {
+    // Inspects the next candidate in listing order; returns false once the page is complete
+    fn visit(&mut self, value: u64, id: u64, load: impl FnOnce() -> Option<Event>) -> bool {
+        // Past the end of the range filter on the ordered field nothing further can match
+        if self.upper.is_some_and(|upper| value > upper) {
+            return false;
+        }
+        if self.events.len() == self.limit || self.scanned == MAX_SCANNED {
+            self.finished = false;
+            return false;
+        }
+
+        self.scanned += 1;
+        self.last_seen = Some(ListCursor {
+            sort_value: value,
+            start_after: id,
+        });
+        if let Some(event) = load() {
+            if self.request.matches(&event) && (self.filter)(&event) {
+                self.events.push(event);
+            }
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::recurrence::{Frequency, RecurrenceRule};
+
+    const HOUR: u64 = 3_600 * 1_000_000_000;
+
+    fn store(event: Event) {
+        reindex(None, Some(&event));
+        STORAGE.with(|s| s.borrow_mut().insert(event.id, event));
+    }
+
+    fn owner(n: u8) -> Principal {
+        Principal::from_slice(&[n])
+    }
+
+    fn ids(response: &ListResponse) -> Vec<u64> {
+        response.events.iter().map(|event| event.id).collect()
+    }
+
+    #[test]
+    fn pages_resume_after_the_cursor() {
+        for id in 1..=5 {
+            store(Event {
+                created_at: 100 - id,
+                ..crate::test_event(id)
+            });
+        }
+        let mut request = ListRequest {
+            limit: Some(2),
+            order_by: Some(ListOrder::CreatedAt),
+            ..Default::default()
+        };
+        let mut pages = Vec::new();
+        loop {
+            let response = list(&request);
+            pages.push(ids(&response));
+            let Some(cursor) = response.next_cursor else {
+                break;
+            };
+            request.cursor = Some(cursor);
+        }
+        assert_eq!(pages, [vec![5, 4], vec![3, 2], vec![1]]);
+    }
+
+    #[test]
+    fn stops_scanning_after_max_scanned_records() {
+        let wanted = MAX_SCANNED as u64 + 10;
+        for id in 1..=wanted {
+            store(Event {
+                owner: owner(if id == wanted { 1 } else { 2 }),
+                ..crate::test_event(id)
+            });
+        }
+        let mut request = ListRequest {
+            owner: Some(owner(1)),
+            ..Default::default()
+        };
+        // The first page gives up before reaching the match, but says where it stopped
+        let response = list(&request);
+        assert!(response.events.is_empty());
+        let Some(cursor) = response.next_cursor else {
+            panic!("a cut-off page should return a cursor");
+        };
+        assert_eq!(cursor.start_after, MAX_SCANNED as u64);
+
+        request.cursor = Some(cursor);
+        let response = list(&request);
+        assert_eq!(ids(&response), [wanted]);
+        assert!(response.next_cursor.is_none());
+    }
+
+    #[test]
+    fn classifies_series_by_their_current_or_next_occurrence() {
+        let now = 10_000 * HOUR;
+        let daily = RecurrenceRule {
+            frequency: Frequency::Daily,
+            interval: None,
+            by_day: Vec::new(),
+            count: None,
+            until: None,
+            exceptions: Vec::new(),
+        };
+        let series = |starts_at: u64| Event {
+            starts_at: Some(starts_at),
+            ends_at: Some(starts_at + HOUR),
+            recurrence: Some(daily.clone()),
+            ..crate::test_event(1)
+        };
+        // Open-ended series that started long ago, between and during occurrences
+        let between = series(now - 100 * 24 * HOUR + 2 * HOUR);
+        assert!(is_upcoming(&between, now) && !is_ongoing(&between, now));
+        let during = series(now - 100 * 24 * HOUR);
+        assert!(is_ongoing(&during, now) && !is_upcoming(&during, now));
+
+        let once = |starts_at: u64| Event {
+            starts_at: Some(starts_at),
+            ends_at: Some(starts_at + HOUR),
+            ..crate::test_event(2)
+        };
+        assert!(is_upcoming(&once(now + 1), now));
+        assert!(is_ongoing(&once(now), now));
+        let over = once(now - HOUR);
+        assert!(!is_upcoming(&over, now) && !is_ongoing(&over, now));
+        let unscheduled = crate::test_event(3);
+        assert!(!is_upcoming(&unscheduled, now) && !is_ongoing(&unscheduled, now));
+    }
+}
@@ -0,0 +1,968 @@
+// Schema versioning and upgrade-time migrations for stored events.
+//
+// Every event written to stable memory is wrapped in a small envelope:
+//
+//     | "EVT" magic (3 bytes) | schema version (u16, big endian) | candid payload |
+//
+// Records written before the envelope existed are plain candid (they start with
+// the "DIDL" candid magic) and are treated as schema version 1. `run` is called
+// from `#[post_upgrade]` and brings every stored record up to the current schema
+// so old layouts never have to be decoded again. It runs to completion inside that
+// one call: there is no partial migration to resume, since a trap rolls back the
+// whole upgrade and leaves the canister on its previous code and data.
+//
+// Optional fields added to `Event` later decode as `null` from older records and
+// need no schema bump. Records that do not decode at all are moved to
+// `UNREADABLE_EVENTS` before the migration steps run, so one corrupted record
+// cannot fail the upgrade.
+//
+// Schema history:
+//   v1  bare candid `Event` with an inline `attendees: Vec<String>`
+//   v2  same record inside the versioned envelope
+//   v3  event bodies moved to a larger map, attendees moved to their own map
+//       (`LEGACY_ATTENDEES` since v6)
+//   v4  `CREATED_INDEX` / `UPDATED_INDEX` backfilled for list_events
+//   v5  `SEARCH_INDEX` backfilled for search_events
+//   v6  attendees re-keyed by (event, occurrence) slot into `ATTENDEES`, with
+//       per-slot counts in `SLOT_COUNTS`
+//   v7  `RSVPS` backfilled with a `Going`/`Waitlisted` answer for every seat and
+//       waitlist entry
+//   v8  owners (including pending transfers and previous owners) stored as
+//       `Principal` instead of their textual form
+//   v9  `Event.version` added for optimistic concurrency; existing events start
+//       at version 1
+//   v10 `CERT_TREE` backfilled for get_event_certified
+//   v11 `SEATS_BY_PRINCIPAL` backfilled for the attendee calendars
+//   v12 events in `REVISIONS` stored in the versioned envelope instead of as
+//       bare candid
+//   v13 `OWNER_INDEX` backfilled for the owner calendars
+
+use candid::{CandidType, Decode, Encode, Principal};
+use ic_cdk::api::time;
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::{BoundedStorable, StableBTreeMap};
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::ops::Bound;
+
+use crate::attendance::{self, WHOLE_EVENT};
+use crate::ownership::{OwnershipTransfer, PreviousOwner};
+use crate::{
+    certification, listing, recurrence, search, Event, Memory, StorablePrincipal,
+    LEGACY_ATTENDEES, LEGACY_STORAGE, MEMORY_MANAGER, REVISIONS, SCHEMA_STATE, STORAGE, TRASH,
+    UNREADABLE_EVENTS,
+};
+
+// The schema version written by this build of the canister
+pub const CURRENT_SCHEMA_VERSION: u16 = 13;
+
+// Schema version of records written before the versioned envelope was introduced
+const LEGACY_SCHEMA_VERSION: u16 = 1;
+
+// Last schema version that kept attendees inline in `LEGACY_STORAGE`
+const INLINE_ATTENDEES_SCHEMA_VERSION: u16 = 2;
+
+// Last schema version without the list_events secondary indexes
+const UNINDEXED_SCHEMA_VERSION: u16 = 3;
+
+// Last schema version without the search index
+const UNSEARCHABLE_SCHEMA_VERSION: u16 = 4;
+
+// Last schema version keying attendees by event only, in `LEGACY_ATTENDEES`
+const PER_EVENT_ATTENDEES_SCHEMA_VERSION: u16 = 5;
+
+// Last schema version without RSVP records
+const UNANSWERED_SCHEMA_VERSION: u16 = 6;
+
+// Last schema version storing owners as text
+const TEXT_PRINCIPALS_SCHEMA_VERSION: u16 = 7;
+
+// Last schema version without event versions
+const UNVERSIONED_SCHEMA_VERSION: u16 = 8;
+
+// Last schema version without the certified event tree
+const UNCERTIFIED_SCHEMA_VERSION: u16 = 9;
+
+// Last schema version without the per-principal seat index
+const UNINDEXED_SEATS_SCHEMA_VERSION: u16 = 10;
+
+// Last schema version storing revisions with a bare candid event
+const UNENVELOPED_REVISIONS_SCHEMA_VERSION: u16 = 11;
+
+// Last schema version without the owner index
+const UNINDEXED_OWNERS_SCHEMA_VERSION: u16 = 12;
+
+// Version given to events that predate event versions
+const FIRST_EVENT_VERSION: u64 = 1;
+
+// Magic bytes marking a versioned event envelope
+const ENVELOPE_MAGIC: &[u8; 3] = b"EVT";
+
+// Size of the envelope header (magic + version)
+const ENVELOPE_HEADER_LEN: usize = ENVELOPE_MAGIC.len() + 2;
+
+// Memories of `STORAGE` and `TRASH`, which the migration also reads as raw bytes
+pub const STORAGE_MEMORY_ID: u8 = 3;
+pub const TRASH_MEMORY_ID: u8 = 22;
+
+// Number of records read from a map before they are rewritten, since a map cannot
+// be written while it is being iterated
+const MIGRATION_BATCH_SIZE: usize = 100;
+
+// Progress of the most recent migration run
+#[derive(CandidType, Clone, Serialize, Deserialize)]
+pub struct MigrationProgress {
+    pub from_version: u16,
+    pub to_version: u16,
+    pub records_total: u64,
+    pub records_migrated: u64,
+    // Records set aside in `UNREADABLE_EVENTS`; absent from runs that predate it
+    pub records_skipped: Option<u64>,
+    pub last_migrated_id: Option<u64>,
+    pub started_at: u64,
+    pub finished_at: Option<u64>,
+}
+
+// Persistent schema bookkeeping kept in its own stable cell
+#[derive(CandidType, Clone, Serialize, Deserialize)]
+pub struct SchemaState {
+    pub stored_version: u16,
+    pub last_migration: Option<MigrationProgress>,
+}
+
+// A canister that predates the schema cell holds legacy records only
+impl Default for SchemaState {
+    fn default() -> Self {
+        Self {
+            stored_version: LEGACY_SCHEMA_VERSION,
+            last_migration: None,
+        }
+    }
+}
+
+impl ic_stable_structures::Storable for SchemaState {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+// Raw bytes of a v1/v2 record in `LEGACY_STORAGE`, which was created with a 1 KiB value cap
+pub struct LegacyEventBytes(Vec<u8>);
+
+impl ic_stable_structures::Storable for LegacyEventBytes {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Borrowed(&self.0)
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Self(bytes.into_owned())
+    }
+}
+
+impl ic_stable_structures::BoundedStorable for LegacyEventBytes {
+    const MAX_SIZE: u32 = 1024;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+// Raw bytes of a record in `STORAGE` or `TRASH`, read without decoding the event
+pub struct EventBytes(Vec<u8>);
+
+impl ic_stable_structures::Storable for EventBytes {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Borrowed(&self.0)
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Self(bytes.into_owned())
+    }
+}
+
+impl BoundedStorable for EventBytes {
+    const MAX_SIZE: u32 = <Event as BoundedStorable>::MAX_SIZE;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+// Event shape of schema versions 1 and 2
+#[derive(CandidType, Deserialize)]
+struct EventV2 {
+    id: u64,
+    event_description: String,
+    owner: String,
+    event_title: String,
+    event_location: String,
+    event_card_imgurl: String,
+    attendees: Vec<String>,
+    created_at: u64,
+    updated_at: Option<u64>,
+}
+
+// Event shape of schema versions 3 to 7; fields added after v3 are optional
+#[derive(CandidType, Deserialize)]
+struct EventV7 {
+    id: u64,
+    event_description: String,
+    owner: String,
+    event_title: String,
+    event_location: String,
+    event_card_imgurl: String,
+    attendee_count: u64,
+    capacity: Option<u32>,
+    starts_at: Option<u64>,
+    ends_at: Option<u64>,
+    time_zone: Option<String>,
+    recurrence: Option<recurrence::RecurrenceRule>,
+    pending_transfer: Option<OwnershipTransferV7>,
+    previous_owners: Option<Vec<PreviousOwnerV7>>,
+    created_at: u64,
+    updated_at: Option<u64>,
+}
+
+#[derive(CandidType, Deserialize)]
+struct OwnershipTransferV7 {
+    to: String,
+    proposed_at: u64,
+    expires_at: u64,
+}
+
+#[derive(CandidType, Deserialize)]
+struct PreviousOwnerV7 {
+    owner: String,
+    owned_from: u64,
+    owned_until: u64,
+}
+
+// Event shape of schema version 8
+#[derive(CandidType, Deserialize)]
+struct EventV8 {
+    id: u64,
+    event_description: String,
+    owner: Principal,
+    event_title: String,
+    event_location: String,
+    event_card_imgurl: String,
+    attendee_count: u64,
+    capacity: Option<u32>,
+    starts_at: Option<u64>,
+    ends_at: Option<u64>,
+    time_zone: Option<String>,
+    recurrence: Option<recurrence::RecurrenceRule>,
+    pending_transfer: Option<OwnershipTransfer>,
+    previous_owners: Option<Vec<PreviousOwner>>,
+    hidden_at: Option<u64>,
+    created_at: u64,
+    updated_at: Option<u64>,
+}
+
+impl From<EventV7> for EventV8 {
+    fn from(event: EventV7) -> Self {
+        Self {
+            id: event.id,
+            event_description: event.event_description,
+            owner: parse_owner(&event.owner),
+            event_title: event.event_title,
+            event_location: event.event_location,
+            event_card_imgurl: event.event_card_imgurl,
+            attendee_count: event.attendee_count,
+            capacity: event.capacity,
+            starts_at: event.starts_at,
+            ends_at: event.ends_at,
+            time_zone: event.time_zone,
+            recurrence: event.recurrence,
+            pending_transfer: event.pending_transfer.map(|transfer| OwnershipTransfer {
+                to: parse_owner(&transfer.to),
+                proposed_at: transfer.proposed_at,
+                expires_at: transfer.expires_at,
+            }),
+            previous_owners: event.previous_owners.map(|owners| {
+                owners
+                    .into_iter()
+                    .map(|previous| PreviousOwner {
+                        owner: parse_owner(&previous.owner),
+                        owned_from: previous.owned_from,
+                        owned_until: previous.owned_until,
+                    })
+                    .collect()
+            }),
+            hidden_at: None,
+            created_at: event.created_at,
+            updated_at: event.updated_at,
+        }
+    }
+}
+
+impl From<EventV8> for Event {
+    fn from(event: EventV8) -> Self {
+        Self {
+            id: event.id,
+            event_description: event.event_description,
+            owner: event.owner,
+            event_title: event.event_title,
+            event_location: event.event_location,
+            event_card_imgurl: event.event_card_imgurl,
+            attendee_count: event.attendee_count,
+            capacity: event.capacity,
+            starts_at: event.starts_at,
+            ends_at: event.ends_at,
+            time_zone: event.time_zone,
+            recurrence: event.recurrence,
+            pending_transfer: event.pending_transfer,
+            previous_owners: event.previous_owners,
+            hidden_at: event.hidden_at,
+            deleted_at: None,
+            created_at: event.created_at,
+            updated_at: event.updated_at,
+            version: FIRST_EVENT_VERSION,
+        }
+    }
+}
+
+// Parses an owner stored as text. Owners were always written from `caller()`, so
+// a malformed one means a corrupted record; it falls back to the anonymous
+// principal, which nobody can act as, instead of failing the whole upgrade.
+fn parse_owner(owner: &str) -> Principal {
+    Principal::from_text(owner).unwrap_or_else(|_| Principal::anonymous())
+}
+
+// Response of the `get_schema_info` query
+#[derive(CandidType, Serialize, Deserialize)]
+pub struct SchemaInfo {
+    pub current_version: u16,
+    pub stored_version: u16,
+    pub migration: Option<MigrationProgress>,
+}
+
+// Encodes an event into a versioned envelope at the current schema version
+pub fn encode_event(event: &Event) -> Vec<u8> {
+    let payload = Encode!(event).unwrap();
+    let mut bytes = Vec::with_capacity(ENVELOPE_HEADER_LEN + payload.len());
+    bytes.extend_from_slice(ENVELOPE_MAGIC);
+    bytes.extend_from_slice(&CURRENT_SCHEMA_VERSION.to_be_bytes());
+    bytes.extend_from_slice(&payload);
+    bytes
+}
+
+// Decodes an event stored at any schema version still readable from `STORAGE`
+pub fn decode_event(bytes: &[u8]) -> Result<Event, String> {
+    let (version, payload) = split_envelope(bytes);
+    let event = match version {
+        // v4 to v7 only changed the structures next to the v3 record
+        UNINDEXED_SCHEMA_VERSION
+        | UNSEARCHABLE_SCHEMA_VERSION
+        | PER_EVENT_ATTENDEES_SCHEMA_VERSION
+        | UNANSWERED_SCHEMA_VERSION
+        | TEXT_PRINCIPALS_SCHEMA_VERSION => {
+            Decode!(payload, EventV7).map(|event| EventV8::from(event).into())
+        }
+        UNVERSIONED_SCHEMA_VERSION => Decode!(payload, EventV8).map(Event::from),
+        // v10 to v13 only changed the structures next to the v9 record
+        UNCERTIFIED_SCHEMA_VERSION
+        | UNINDEXED_SEATS_SCHEMA_VERSION
+        | UNENVELOPED_REVISIONS_SCHEMA_VERSION
+        | UNINDEXED_OWNERS_SCHEMA_VERSION
+        | CURRENT_SCHEMA_VERSION => Decode!(payload, Event),
+        other => return Err(format!("unsupported event schema version {}", other)),
+    };
+    event.map_err(|err| format!("cannot decode a v{} event: {}", version, err))
+}
+
+// Decodes a record from `LEGACY_STORAGE`
+fn decode_legacy_event(bytes: &[u8]) -> Result<EventV2, String> {
+    let (version, payload) = split_envelope(bytes);
+    match version {
+        // v1 records are bare candid; v2 only added the envelope around the same record
+        LEGACY_SCHEMA_VERSION | INLINE_ATTENDEES_SCHEMA_VERSION => Decode!(payload, EventV2)
+            .map_err(|err| format!("cannot decode a v{} legacy event: {}", version, err)),
+        other => Err(format!("unsupported legacy event schema version {}", other)),
+    }
+}
+
+// Splits stored bytes into their schema version and candid payload
+fn split_envelope(bytes: &[u8]) -> (u16, &[u8]) {
+    if bytes.len() >= ENVELOPE_HEADER_LEN && bytes.starts_with(ENVELOPE_MAGIC) {
+        let version = u16::from_be_bytes([bytes[3], bytes[4]]);
+        (version, &bytes[ENVELOPE_HEADER_LEN..])
+    } else {
+        (LEGACY_SCHEMA_VERSION, bytes)
+    }
+}
+
+// Reports the schema version of the stored data and the latest migration run
+pub fn schema_info() -> SchemaInfo {
+    SCHEMA_STATE.with(|state| {
+        let state = state.borrow().get().clone();
+        SchemaInfo {
+            current_version: CURRENT_SCHEMA_VERSION,
+            stored_version: state.stored_version,
+            migration: state.last_migration,
+        }
+    })
+}
+
+// Marks a freshly installed canister as already being at the current schema
+pub fn init_schema() {
+    set_state(SchemaState {
+        stored_version: CURRENT_SCHEMA_VERSION,
+        last_migration: None,
+    });
+}
+
+// Brings every stored record up to the current schema version and records the run
+// for `get_schema_info`
+pub fn run() {
+    let state = SCHEMA_STATE.with(|state| state.borrow().get().clone());
+    if state.stored_version >= CURRENT_SCHEMA_VERSION {
+        return;
+    }
+
+    let mut progress = MigrationProgress {
+        from_version: state.stored_version,
+        to_version: CURRENT_SCHEMA_VERSION,
+        records_total: 0,
+        records_migrated: 0,
+        records_skipped: Some(0),
+        last_migrated_id: None,
+        started_at: time(),
+        finished_at: None,
+    };
+
+    set_aside_unreadable(&mut progress);
+
+    if state.stored_version <= INLINE_ATTENDEES_SCHEMA_VERSION {
+        let total = LEGACY_STORAGE.with(|s| s.borrow().len());
+        run_step(&mut progress, total, split_legacy_events);
+    }
+    if state.stored_version <= UNSEARCHABLE_SCHEMA_VERSION {
+        let total = STORAGE.with(|s| s.borrow().len());
+        run_step(&mut progress, total, index_events);
+    }
+    if state.stored_version <= PER_EVENT_ATTENDEES_SCHEMA_VERSION {
+        let total = STORAGE.with(|s| s.borrow().len());
+        run_step(&mut progress, total, move_attendees_to_slots);
+    }
+    if state.stored_version <= UNANSWERED_SCHEMA_VERSION {
+        let total = STORAGE.with(|s| s.borrow().len());
+        run_step(&mut progress, total, backfill_rsvps);
+    }
+    if state.stored_version <= UNVERSIONED_SCHEMA_VERSION {
+        let total = STORAGE.with(|s| s.borrow().len());
+        run_step(&mut progress, total, rewrite_events);
+    }
+    if state.stored_version <= UNCERTIFIED_SCHEMA_VERSION {
+        let total = STORAGE.with(|s| s.borrow().len());
+        run_step(&mut progress, total, certify_events);
+    }
+    if state.stored_version <= UNINDEXED_SEATS_SCHEMA_VERSION {
+        let total = STORAGE.with(|s| s.borrow().len());
+        run_step(&mut progress, total, index_seats);
+        // Trashed events keep their seats for a restore
+        let total = TRASH.with(|t| t.borrow().len());
+        run_step(&mut progress, total, index_trashed_seats);
+    }
+    if state.stored_version <= UNENVELOPED_REVISIONS_SCHEMA_VERSION {
+        let total = REVISIONS.with(|r| r.borrow().len());
+        run_step(&mut progress, total, rewrite_revisions);
+    }
+    if state.stored_version <= UNINDEXED_OWNERS_SCHEMA_VERSION {
+        let total = STORAGE.with(|s| s.borrow().len());
+        run_step(&mut progress, total, index_owners);
+    }
+
+    progress.finished_at = Some(time());
+    set_state(SchemaState {
+        stored_version: CURRENT_SCHEMA_VERSION,
+        last_migration: Some(progress),
+    });
+}
+
+// Moves every record of `LEGACY_STORAGE`, `STORAGE` and `TRASH` that does not decode
+// into `UNREADABLE_EVENTS`. The event maps are read through raw views of their
+// memories, so this has to run before `STORAGE` or `TRASH` is first used in the call.
+fn set_aside_unreadable(progress: &mut MigrationProgress) {
+    let unreadable: Vec<(u64, String)> = LEGACY_STORAGE.with(|s| {
+        s.borrow()
+            .iter()
+            .filter_map(|(id, bytes)| decode_legacy_event(&bytes.0).err().map(|err| (id, err)))
+            .collect()
+    });
+    for (id, err) in unreadable {
+        if let Some(bytes) = LEGACY_STORAGE.with(|s| s.borrow_mut().remove(&id)) {
+            set_aside(progress, id, bytes.0, &err);
+        }
+    }
+
+    for memory_id in [STORAGE_MEMORY_ID, TRASH_MEMORY_ID] {
+        let memory = MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(memory_id)));
+        let mut events: StableBTreeMap<u64, EventBytes, Memory> = StableBTreeMap::init(memory);
+        let unreadable: Vec<(u64, String)> = events
+            .iter()
+            .filter_map(|(id, bytes)| decode_event(&bytes.0).err().map(|err| (id, err)))
+            .collect();
+        for (id, err) in unreadable {
+            if let Some(bytes) = events.remove(&id) {
+                set_aside(progress, id, bytes.0, &err);
+            }
+        }
+    }
+}
+
+fn set_aside(progress: &mut MigrationProgress, id: u64, bytes: Vec<u8>, err: &str) {
+    ic_cdk::println!("Setting aside unreadable event {}: {}", id, err);
+    UNREADABLE_EVENTS.with(|u| u.borrow_mut().insert(id, EventBytes(bytes)));
+    *progress.records_skipped.get_or_insert(0) += 1;
+}
+
+// Runs one migration step: calls `migrate_batch` with the last migrated id until
+// it has nothing left to migrate
+fn run_step(
+    progress: &mut MigrationProgress,
+    total: u64,
+    mut migrate_batch: impl FnMut(Option<u64>) -> Vec<u64>,
+) {
+    progress.records_total += total;
+    progress.last_migrated_id = None;
+    loop {
+        let migrated = migrate_batch(progress.last_migrated_id);
+        if migrated.is_empty() {
+            break;
+        }
+
+        progress.records_migrated += migrated.len() as u64;
+        progress.last_migrated_id = migrated.last().copied();
+    }
+}
+
+// v2 -> v3: moves a batch of events out of `LEGACY_STORAGE`, writing the body to
+// `STORAGE` and every inline attendee to `LEGACY_ATTENDEES`
+fn split_legacy_events(after: Option<u64>) -> Vec<u64> {
+    let batch: Vec<(u64, LegacyEventBytes)> = LEGACY_STORAGE.with(|s| {
+        let s = s.borrow();
+        let range = match after {
+            Some(id) => (Bound::Excluded(id), Bound::Unbounded),
+            None => (Bound::Unbounded, Bound::Unbounded),
+        };
+        s.range(range).take(MIGRATION_BATCH_SIZE).collect()
+    });
+
+    let mut migrated = Vec::with_capacity(batch.len());
+    for (id, bytes) in batch {
+        // Records that do not decode were set aside before the steps ran
+        let legacy = decode_legacy_event(&bytes.0).unwrap_or_else(|msg| ic_cdk::trap(&msg));
+
+        // The join time was never recorded, so the event creation time stands in for it
+        let mut attendee_count = 0;
+        LEGACY_ATTENDEES.with(|a| {
+            let mut a = a.borrow_mut();
+            for attendee in &legacy.attendees {
+                if let Ok(principal) = Principal::from_text(attendee) {
+                    let previous = a.insert((id, StorablePrincipal(principal)), legacy.created_at);
+                    if previous.is_none() {
+                        attendee_count += 1;
+                    }
+                }
+            }
+        });
+
+        let event = Event {
+            id: legacy.id,
+            event_description: legacy.event_description,
+            owner: parse_owner(&legacy.owner),
+            event_title: legacy.event_title,
+            event_location: legacy.event_location,
+            event_card_imgurl: legacy.event_card_imgurl,
+            attendee_count,
+            capacity: None,
+            starts_at: None,
+            ends_at: None,
+            time_zone: None,
+            recurrence: None,
+            pending_transfer: None,
+            previous_owners: None,
+            hidden_at: None,
+            deleted_at: None,
+            created_at: legacy.created_at,
+            updated_at: legacy.updated_at,
+            version: FIRST_EVENT_VERSION,
+        };
+        STORAGE.with(|s| s.borrow_mut().insert(id, event));
+        LEGACY_STORAGE.with(|s| s.borrow_mut().remove(&id));
+        migrated.push(id);
+    }
+    migrated
+}
+
+// v3 -> v4 and v4 -> v5: adds a batch of events to the secondary indexes.
+// Index writes are idempotent, so one pass serves both versions.
+fn index_events(after: Option<u64>) -> Vec<u64> {
+    let batch: Vec<(u64, Event)> = STORAGE.with(|s| {
+        let s = s.borrow();
+        let range = match after {
+            Some(id) => (Bound::Excluded(id), Bound::Unbounded),
+            None => (Bound::Unbounded, Bound::Unbounded),
+        };
+        s.range(range).take(MIGRATION_BATCH_SIZE).collect()
+    });
+
+    batch
+        .into_iter()
+        .map(|(id, event)| {
+            listing::reindex(None, Some(&event));
+            search::reindex(None, Some(&event));
+            id
+        })
+        .collect()
+}
+
+fn set_state(state: SchemaState) {
+    SCHEMA_STATE
+        .with(|s| s.borrow_mut().set(state))
+        .expect("cannot update schema state");
+}
+
+// v5 -> v6: moves the attendees of a batch of events from `LEGACY_ATTENDEES` into
+// the whole-event slot of `ATTENDEES`
+fn move_attendees_to_slots(after: Option<u64>) -> Vec<u64> {
+    let ids: Vec<u64> = STORAGE.with(|s| {
+        let s = s.borrow();
+        let range = match after {
+            Some(id) => (Bound::Excluded(id), Bound::Unbounded),
+            None => (Bound::Unbounded, Bound::Unbounded),
+        };
+        s.range(range)
+            .take(MIGRATION_BATCH_SIZE)
+            .map(|(id, _)| id)
+            .collect()
+    });
+
+    for &id in &ids {
+        let first = StorablePrincipal(Principal::from_slice(&[]));
+        let entries: Vec<((u64, StorablePrincipal), u64)> = LEGACY_ATTENDEES.with(|a| {
+            a.borrow()
+                .range((id, first)..)
+                .take_while(|((event_id, _), _)| *event_id == id)
+                .collect()
+        });
+        for ((_, principal), joined_at) in entries {
+            attendance::add((id, WHOLE_EVENT), principal.0, joined_at);
+            LEGACY_ATTENDEES.with(|a| a.borrow_mut().remove(&(id, principal)));
+        }
+    }
+    ids
+}
+
+// Records an RSVP for every seat and waitlist entry of a batch of events
+fn backfill_rsvps(after: Option<u64>) -> Vec<u64> {
+    let ids: Vec<u64> = STORAGE.with(|s| {
+        let s = s.borrow();
+        let range = match after {
+            Some(id) => (Bound::Excluded(id), Bound::Unbounded),
+            None => (Bound::Unbounded, Bound::Unbounded),
+        };
+        s.range(range)
+            .take(MIGRATION_BATCH_SIZE)
+            .map(|(id, _)| id)
+            .collect()
+    });
+
+    for &id in &ids {
+        attendance::backfill_rsvps(id);
+    }
+    ids
+}
+
+// v7 -> v8 and v8 -> v9: rewrites a batch of events at the current schema, with
+// owners stored as principals and a version set; decoding already converted them
+fn rewrite_events(after: Option<u64>) -> Vec<u64> {
+    let batch: Vec<(u64, Event)> = STORAGE.with(|s| {
+        let s = s.borrow();
+        let range = match after {
+            Some(id) => (Bound::Excluded(id), Bound::Unbounded),
+            None => (Bound::Unbounded, Bound::Unbounded),
+        };
+        s.range(range).take(MIGRATION_BATCH_SIZE).collect()
+    });
+
+    batch
+        .into_iter()
+        .map(|(id, event)| {
+            STORAGE.with(|s| s.borrow_mut().insert(id, event));
+            id
+        })
+        .collect()
+}
+
+// v9 -> v10: adds a batch of events to the certified tree. Leaf writes are
+// idempotent, and the steps before this one may have rewritten events, so every
+// older version runs it last.
+fn certify_events(after: Option<u64>) -> Vec<u64> {
+    let batch: Vec<(u64, Event)> = STORAGE.with(|s| {
+        let s = s.borrow();
+        let range = match after {
+            Some(id) => (Bound::Excluded(id), Bound::Unbounded),
+            None => (Bound::Unbounded, Bound::Unbounded),
+        };
+        s.range(range).take(MIGRATION_BATCH_SIZE).collect()
+    });
+
+    batch
+        .into_iter()
+        .map(|(id, event)| {
+            certification::update(id, Some(&event));
+            id
+        })
+        .collect()
+}
+
+// v10 -> v11: indexes the seats of a batch of events by principal
+fn index_seats(after: Option<u64>) -> Vec<u64> {
+    let ids: Vec<u64> = STORAGE.with(|s| {
+        let s = s.borrow();
+        let range = match after {
+            Some(id) => (Bound::Excluded(id), Bound::Unbounded),
+            None => (Bound::Unbounded, Bound::Unbounded),
+        };
+        s.range(range)
+            .take(MIGRATION_BATCH_SIZE)
+            .map(|(id, _)| id)
+            .collect()
+    });
+
+    for &id in &ids {
+        attendance::index_seats(id);
+    }
+    ids
+}
+
+// v10 -> v11: indexes the seats of a batch of trashed events by principal
+fn index_trashed_seats(after: Option<u64>) -> Vec<u64> {
+    let ids: Vec<u64> = TRASH.with(|t| {
+        let t = t.borrow();
+        let range = match after {
+            Some(id) => (Bound::Excluded(id), Bound::Unbounded),
+            None => (Bound::Unbounded, Bound::Unbounded),
+        };
+        t.range(range)
+            .take(MIGRATION_BATCH_SIZE)
+            .map(|(id, _)| id)
+            .collect()
+    });
+
+    for &id in &ids {
+        attendance::index_seats(id);
+    }
+    ids
+}
+
+// v11 -> v12: rewrites a batch of revisions, which decode from either layout and are
+// written back with an enveloped event. Returns the event id of every rewritten
+// revision; a batch always ends with the last revision of an event, so the next one
+// starts after it.
+fn rewrite_revisions(after: Option<u64>) -> Vec<u64> {
+    REVISIONS.with(|r| {
+        let mut r = r.borrow_mut();
+        let start = match after {
+            Some(id) => Bound::Excluded((id, u64::MAX)),
+            None => Bound::Unbounded,
+        };
+        let mut batch: Vec<_> =
+            r.range((start, Bound::Unbounded)).take(MIGRATION_BATCH_SIZE).collect();
+        if let Some(&(last, _)) = batch.last() {
+            let rest = (Bound::Excluded(last), Bound::Included((last.0, u64::MAX)));
+            batch.extend(r.range(rest));
+        }
+        batch
+            .into_iter()
+            .map(|(key, revision)| {
+                r.insert(key, revision);
+                key.0
+            })
+            .collect()
+    })
+}
+
+// v12 -> v13: adds a batch of events to the owner index
+fn index_owners(after: Option<u64>) -> Vec<u64> {
+    let batch: Vec<(u64, Event)> = STORAGE.with(|s| {
+        let s = s.borrow();
+        let range = match after {
+            Some(id) => (Bound::Excluded(id), Bound::Unbounded),
+            None => (Bound::Unbounded, Bound::Unbounded),
+        };
+        s.range(range).take(MIGRATION_BATCH_SIZE).collect()
+    });
+
+    batch
+        .into_iter()
+        .map(|(id, event)| {
+            listing::reindex_owner(None, Some(&event));
+            id
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn envelope(version: u16, payload: Vec<u8>) -> Vec<u8> {
+        let mut bytes = ENVELOPE_MAGIC.to_vec();
+        bytes.extend_from_slice(&version.to_be_bytes());
+        bytes.extend(payload);
+        bytes
+    }
+
+    fn principal(byte: u8) -> Principal {
+        Principal::from_slice(&[byte; 10])
+    }
+
+    fn event_v7() -> EventV7 {
+        EventV7 {
+            id: 4,
+            event_description: "Description".to_string(),
+            owner: principal(1).to_text(),
+            event_title: "Title".to_string(),
+            event_location: "Location".to_string(),
+            event_card_imgurl: "https://example.com/card.png".to_string(),
+            attendee_count: 2,
+            capacity: Some(10),
+            starts_at: Some(100),
+            ends_at: Some(200),
+            time_zone: Some("UTC".to_string()),
+            recurrence: None,
+            pending_transfer: Some(OwnershipTransferV7 {
+                to: principal(2).to_text(),
+                proposed_at: 5,
+                expires_at: 6,
+            }),
+            previous_owners: Some(vec![PreviousOwnerV7 {
+                owner: principal(3).to_text(),
+                owned_from: 1,
+                owned_until: 2,
+            }]),
+            created_at: 1,
+            updated_at: Some(3),
+        }
+    }
+
+    fn assert_converted(event: &Event) {
+        assert_eq!(event.id, 4);
+        assert_eq!(event.event_title, "Title");
+        assert_eq!(event.owner, principal(1));
+        assert_eq!(event.attendee_count, 2);
+        assert_eq!(event.capacity, Some(10));
+        assert_eq!((event.starts_at, event.ends_at), (Some(100), Some(200)));
+        let Some(transfer) = &event.pending_transfer else {
+            panic!("transfer should be kept");
+        };
+        assert_eq!((transfer.to, transfer.proposed_at, transfer.expires_at), (principal(2), 5, 6));
+        let Some([previous]) = event.previous_owners.as_deref() else {
+            panic!("previous owner should be kept");
+        };
+        assert_eq!(previous.owner, principal(3));
+        assert_eq!((previous.owned_from, previous.owned_until), (1, 2));
+        assert_eq!((event.hidden_at, event.deleted_at), (None, None));
+        assert_eq!((event.created_at, event.updated_at), (1, Some(3)));
+        assert_eq!(event.version, FIRST_EVENT_VERSION);
+    }
+
+    #[test]
+    fn splits_enveloped_and_bare_records() {
+        let payload = Encode!(&event_v7()).unwrap();
+        assert_eq!(split_envelope(&payload), (LEGACY_SCHEMA_VERSION, payload.as_slice()));
+        let bytes = envelope(UNINDEXED_SCHEMA_VERSION, payload.clone());
+        assert_eq!(split_envelope(&bytes), (UNINDEXED_SCHEMA_VERSION, payload.as_slice()));
+        // Too short to hold a header
+        assert_eq!(split_envelope(b"EVT"), (LEGACY_SCHEMA_VERSION, b"EVT".as_slice()));
+    }
+
+    #[test]
+    fn decodes_text_owner_records() {
+        for version in UNINDEXED_SCHEMA_VERSION..=TEXT_PRINCIPALS_SCHEMA_VERSION {
+            let bytes = envelope(version, Encode!(&event_v7()).unwrap());
+            assert_converted(&decode_event(&bytes).unwrap());
+        }
+    }
+
+    #[test]
+    fn decodes_unversioned_records() {
+        let event = EventV8 {
+            hidden_at: Some(7),
+            ..EventV8::from(event_v7())
+        };
+        let bytes = envelope(UNVERSIONED_SCHEMA_VERSION, Encode!(&event).unwrap());
+        let decoded = decode_event(&bytes).unwrap();
+        assert_eq!(decoded.hidden_at, Some(7));
+        assert_converted(&Event {
+            hidden_at: None,
+            ..decoded
+        });
+    }
+
+    #[test]
+    fn decodes_current_layout_records() {
+        let event = Event {
+            event_title: "Title".to_string(),
+            deleted_at: Some(8),
+            version: 3,
+            ..crate::test_event(4)
+        };
+        for version in UNCERTIFIED_SCHEMA_VERSION..=CURRENT_SCHEMA_VERSION {
+            let decoded = decode_event(&envelope(version, Encode!(&event).unwrap())).unwrap();
+            assert_eq!(Encode!(&decoded).unwrap(), Encode!(&event).unwrap());
+        }
+        let decoded = decode_event(&encode_event(&event)).unwrap();
+        assert_eq!(Encode!(&decoded).unwrap(), Encode!(&event).unwrap());
+    }
+
+    #[test]
+    fn rejects_records_it_cannot_read() {
+        let event = Encode!(&crate::test_event(4)).unwrap();
+        let Err(msg) = decode_event(&envelope(CURRENT_SCHEMA_VERSION + 1, event.clone())) else {
+            panic!("a newer schema should not decode");
+        };
+        assert_eq!(msg, format!("unsupported event schema version {}", CURRENT_SCHEMA_VERSION + 1));
+        // Records of the legacy map never belong in `STORAGE`
+        assert!(decode_event(&event).is_err());
+        assert!(decode_event(&envelope(CURRENT_SCHEMA_VERSION, b"DIDL".to_vec())).is_err());
+        assert!(decode_legacy_event(&envelope(UNINDEXED_SCHEMA_VERSION, event)).is_err());
+    }
+
+    #[test]
+    fn sets_aside_records_it_cannot_read() {
+        let memory = MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(STORAGE_MEMORY_ID)));
+        let mut events: StableBTreeMap<u64, EventBytes, Memory> = StableBTreeMap::init(memory);
+        events.insert(1, EventBytes(encode_event(&crate::test_event(1))));
+        let unreadable = envelope(CURRENT_SCHEMA_VERSION + 1, Vec::new());
+        events.insert(2, EventBytes(unreadable.clone()));
+        drop(events);
+
+        let mut progress = MigrationProgress {
+            from_version: UNINDEXED_OWNERS_SCHEMA_VERSION,
+            to_version: CURRENT_SCHEMA_VERSION,
+            records_total: 0,
+            records_migrated: 0,
+            records_skipped: Some(0),
+            last_migrated_id: None,
+            started_at: 0,
+            finished_at: None,
+        };
+        set_aside_unreadable(&mut progress);
+        assert_eq!(progress.records_skipped, Some(1));
+        let stored: Vec<u64> = STORAGE.with(|s| s.borrow().iter().map(|(id, _)| id).collect());
+        assert_eq!(stored, [1]);
+        let set_aside = UNREADABLE_EVENTS.with(|u| u.borrow().get(&2)).map(|bytes| bytes.0);
+        assert_eq!(set_aside, Some(unreadable));
+    }
+
+    #[test]
+    fn malformed_text_owners_become_anonymous() {
+        let event = EventV8::from(EventV7 {
+            owner: "not a principal".to_string(),
+            ..event_v7()
+        });
+        assert_eq!(event.owner, Principal::anonymous());
+    }
+}
@@ -0,0 +1,106 @@
+// Two-step ownership transfer.
+//
+// The owner proposes a new owner, who has until the proposal expires to accept
+// it. Only one proposal is pending at a time; a new proposal replaces it. On
+// acceptance the outgoing owner is appended to `Event.previous_owners`, which
+// keeps the newest `MAX_PREVIOUS_OWNERS`, and any role the new owner held on the
+// event is dropped, as owning implies every role.
+
+use candid::{CandidType, Principal};
+use serde::{Deserialize, Serialize};
+
+use crate::{roles, Error, Event};
+
+// How long a proposal stays open when the owner does not pick a window: 7 days
+const DEFAULT_TRANSFER_WINDOW: u64 = 7 * 24 * 60 * 60 * 1_000_000_000;
+
+// Longest window an owner may pick: 30 days
+const MAX_TRANSFER_WINDOW: u64 = 30 * 24 * 60 * 60 * 1_000_000_000;
+
+// Previous owners kept on an event; older ones are dropped as transfers are accepted
+const MAX_PREVIOUS_OWNERS: usize = 20;
+
+// An ownership transfer waiting for the proposed owner to accept it
+#[derive(CandidType, Clone, Serialize, Deserialize)]
+pub struct OwnershipTransfer {
+    pub to: Principal,
+    pub proposed_at: u64,
+    pub expires_at: u64,
+}
+
+// A principal that owned the event before its current owner
+#[derive(CandidType, Clone, Serialize, Deserialize)]
+pub struct PreviousOwner {
+    pub owner: Principal,
+    pub owned_from: u64,
+    pub owned_until: u64,
+}
+
+// Records a transfer of `event` to `to`, open for `window` nanoseconds
+pub fn propose(event: &mut Event, to: Principal, window: Option<u64>, now: u64) -> Result<(), Error> {
+    if event.owner == to {
+        return Err(Error::invalid_field("new_owner", "is already the owner of the event"));
+    }
+    if to == Principal::anonymous() {
+        return Err(Error::invalid_field("new_owner", "must not be the anonymous principal"));
+    }
+    let window = window.unwrap_or(DEFAULT_TRANSFER_WINDOW);
+    if window == 0 || window > MAX_TRANSFER_WINDOW {
+        return Err(Error::invalid_field(
+            "expires_in",
+            format!("must be between 1 and {} nanoseconds", MAX_TRANSFER_WINDOW),
+        ));
+    }
+    event.pending_transfer = Some(OwnershipTransfer {
+        to,
+        proposed_at: now,
+        expires_at: now.saturating_add(window),
+    });
+    Ok(())
+}
+
+// Makes `principal` the owner of `event` if a live proposal names them
+pub fn accept(event: &mut Event, principal: Principal, now: u64) -> Result<(), Error> {
+    let transfer = match &event.pending_transfer {
+        Some(transfer) if transfer.to == principal => transfer,
+        _ => {
+            return Err(Error::NotFound {
+                msg: format!("No ownership transfer of the event with id={} is pending for you", event.id),
+            })
+        }
+    };
+    if transfer.expires_at <= now {
+        return Err(Error::NotFound {
+            msg: format!("The ownership transfer of the event with id={} expired at {}", event.id, transfer.expires_at),
+        });
+    }
+
+    let owned_from = event
+        .previous_owners
+        .as_ref()
+        .and_then(|owners| owners.last())
+        .map_or(event.created_at, |previous| previous.owned_until);
+    let previous = PreviousOwner {
+        owner: std::mem::replace(&mut event.owner, principal),
+        owned_from,
+        owned_until: now,
+    };
+    let previous_owners = event.previous_owners.get_or_insert_with(Vec::new);
+    previous_owners.push(previous);
+    if previous_owners.len() > MAX_PREVIOUS_OWNERS {
+        previous_owners.drain(..previous_owners.len() - MAX_PREVIOUS_OWNERS);
+    }
+    event.pending_transfer = None;
+    roles::revoke(event.id, principal);
+    Ok(())
+}
+
+// Withdraws the pending proposal of `event`
+pub fn cancel(event: &mut Event) -> Result<(), Error> {
+    if event.pending_transfer.take().is_none() {
+        return Err(Error::NotFound {
+            msg: format!("No ownership transfer of the event with id={} is pending", event.id),
+        });
+    }
+    Ok(())
+}
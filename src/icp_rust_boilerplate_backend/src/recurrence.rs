@@ -0,0 +1,689 @@
+// RRULE-style recurrence rules for repeating events.
+//
+// A rule repeats the event's first occurrence (`starts_at`..`ends_at`) daily,
+// weekly or monthly every `interval` periods, optionally on a set of weekdays,
+// until `count` occurrences have been generated or `until` has passed.
+// `exceptions` lists occurrence start times that are skipped (like EXDATE);
+// as in RFC 5545 they still count towards `count`.
+//
+// Occurrences are computed in UTC from the first start time. Monthly rules keep
+// the day of month of the first occurrence and skip months that don't have it.
+// Since expanding in UTC would shift local start times across daylight saving
+// changes, recurring events must use a UTC time zone (see `is_utc`).
+
+use candid::CandidType;
+use serde::{Deserialize, Serialize};
+
+use crate::Event;
+
+const NANOS_PER_DAY: u64 = 86_400 * 1_000_000_000;
+
+// Time zone names without an offset from UTC or daylight saving time
+const UTC_TIME_ZONES: [&str; 4] = ["UTC", "GMT", "Etc/UTC", "Etc/GMT"];
+
+// Upper bounds keeping rule expansion cheap
+const MAX_COUNT: u32 = 1_000;
+const MAX_INTERVAL: u32 = 1_000;
+const MAX_EXCEPTIONS: usize = 200;
+
+// Most recurrence periods walked while expanding a rule
+const MAX_PERIODS: u64 = 50_000;
+
+#[derive(CandidType, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub enum Frequency {
+    Daily,
+    Weekly,
+    Monthly,
+}
+
+#[derive(CandidType, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Weekday {
+    Monday,
+    Tuesday,
+    Wednesday,
+    Thursday,
+    Friday,
+    Saturday,
+    Sunday,
+}
+
+impl Weekday {
+    // Days since the Monday starting the week
+    fn offset(self) -> u64 {
+        self as u64
+    }
+}
+
+#[derive(CandidType, Clone, Serialize, Deserialize)]
+pub struct RecurrenceRule {
+    pub frequency: Frequency,
+    // Repeat every `interval` days/weeks/months; 1 when unset
+    pub interval: Option<u32>,
+    // Weekdays a weekly rule falls on; the weekday of the first occurrence when empty
+    pub by_day: Vec<Weekday>,
+    // Stop after this many occurrences
+    pub count: Option<u32>,
+    // No occurrence starts after this time (nanoseconds since the epoch)
+    pub until: Option<u64>,
+    // Start times of cancelled occurrences
+    pub exceptions: Vec<u64>,
+}
+
+// A single occurrence of an event
+#[derive(CandidType, Serialize, Deserialize)]
+pub struct Occurrence {
+    pub starts_at: u64,
+    pub ends_at: u64,
+    pub attendee_count: u64,
+}
+
+impl RecurrenceRule {
+    fn interval(&self) -> u64 {
+        self.interval.unwrap_or(1).max(1) as u64
+    }
+}
+
+// Whether a time zone keeps local time equal to UTC all year round
+pub fn is_utc(time_zone: &str) -> bool {
+    UTC_TIME_ZONES.contains(&time_zone)
+}
+
+// Checks that a rule is well formed and cheap to expand
+pub fn validate(rule: &RecurrenceRule, starts_at: u64) -> Result<(), String> {
+    if rule.interval.is_some_and(|interval| interval == 0 || interval > MAX_INTERVAL) {
+        return Err(format!("interval must be between 1 and {}", MAX_INTERVAL));
+    }
+    if rule.count.is_some_and(|count| count == 0 || count > MAX_COUNT) {
+        return Err(format!("count must be between 1 and {}", MAX_COUNT));
+    }
+    if rule.count.is_some() && rule.until.is_some() {
+        return Err("count and until are mutually exclusive".to_string());
+    }
+    if rule.until.is_some_and(|until| until < starts_at) {
+        return Err("until must not be before starts_at".to_string());
+    }
+    if !rule.by_day.is_empty() && rule.frequency != Frequency::Weekly {
+        return Err("by_day is only supported on weekly rules".to_string());
+    }
+    if rule.exceptions.len() > MAX_EXCEPTIONS {
+        return Err(format!("at most {} exceptions are allowed", MAX_EXCEPTIONS));
+    }
+    Ok(())
+}
+
+// Days since 1970-01-01 of a proleptic Gregorian date (Howard Hinnant's algorithm)
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year - era * 400;
+    let month = month as i64;
+    let doy = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + day as i64 - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+// Proleptic Gregorian (year, month, day) of a day count since 1970-01-01
+pub fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+fn days_in_month(year: i64, month: u32) -> u32 {
+    let (next_year, next_month) = if month == 12 { (year + 1, 1) } else { (year, month + 1) };
+    (days_from_civil(next_year, next_month, 1) - days_from_civil(year, month, 1)) as u32
+}
+
+// Iterator over occurrence start times of a rule, before exceptions are removed
+struct Starts<'a> {
+    rule: &'a RecurrenceRule,
+    first: u64,
+    period: u64,
+    // Start times generated for the current period, latest first
+    pending: Vec<u64>,
+    generated: u32,
+}
+
+impl<'a> Starts<'a> {
+    fn new(rule: &'a RecurrenceRule, first: u64) -> Self {
+        Self {
+            rule,
+            first,
+            period: 0,
+            pending: Vec::new(),
+            generated: 0,
+        }
+    }
+
+    // Fills `pending` with the start times falling in the current period; ends the
+    // series once start times no longer fit in a u64
+    fn expand_period(&mut self) {
+        if self.period_starts().is_none() {
+            self.pending.clear();
+            self.period = MAX_PERIODS;
+            return;
+        }
+        self.period += 1;
+    }
+
+    // Start times falling in period `period`, latest first
+    fn in_period(rule: &'a RecurrenceRule, first: u64, period: u64) -> Vec<u64> {
+        let mut starts = Self::new(rule, first);
+        starts.period = period;
+        starts.period_starts();
+        starts.pending
+    }
+
+    fn period_starts(&mut self) -> Option<()> {
+        let first_day = self.first / NANOS_PER_DAY;
+        let time_of_day = self.first % NANOS_PER_DAY;
+        let step = self.period.checked_mul(self.rule.interval())?;
+        let at_day = |day: u64| day.checked_mul(NANOS_PER_DAY)?.checked_add(time_of_day);
+
+        match self.rule.frequency {
+            Frequency::Daily => {
+                self.pending.push(at_day(first_day.checked_add(step)?)?);
+            }
+            Frequency::Weekly => {
+                // 1970-01-01 was a Thursday, three days after the start of its week, so
+                // weeks are counted from the first start and days before the epoch skipped
+                let days_into_week = (first_day + 3) % 7;
+                let week = first_day.checked_add(step.checked_mul(7)?)?;
+                let mut days = self.rule.by_day.clone();
+                if days.is_empty() {
+                    days.push(weekday_of(first_day));
+                }
+                days.sort();
+                days.dedup();
+                for day in days.into_iter().rev() {
+                    let day = week.checked_add(day.offset())?.checked_sub(days_into_week);
+                    let Some(day) = day else {
+                        continue;
+                    };
+                    let start = at_day(day)?;
+                    if start >= self.first {
+                        self.pending.push(start);
+                    }
+                }
+            }
+            Frequency::Monthly => {
+                let (year, month, day) = civil_from_days(first_day as i64);
+                let months = (month - 1) as u64 + step;
+                let year = year.checked_add(i64::try_from(months / 12).ok()?)?;
+                let month = (months % 12) as u32 + 1;
+                if day <= days_in_month(year, month) {
+                    let days = u64::try_from(days_from_civil(year, month, day)).ok()?;
+                    self.pending.push(at_day(days)?);
+                }
+            }
+        }
+        Some(())
+    }
+}
+
+impl Iterator for Starts<'_> {
+    type Item = u64;
+
+    fn next(&mut self) -> Option<u64> {
+        while self.pending.is_empty() {
+            if self.period >= MAX_PERIODS {
+                return None;
+            }
+            self.expand_period();
+        }
+        // Weekly periods are generated in reverse so the earliest start pops first
+        let start = self.pending.pop()?;
+        if self.rule.count.is_some_and(|count| self.generated >= count)
+            || self.rule.until.is_some_and(|until| start > until)
+        {
+            self.pending.clear();
+            self.period = MAX_PERIODS;
+            return None;
+        }
+        self.generated += 1;
+        Some(start)
+    }
+}
+
+fn weekday_of(days: u64) -> Weekday {
+    match (days + 3) % 7 {
+        0 => Weekday::Monday,
+        1 => Weekday::Tuesday,
+        2 => Weekday::Wednesday,
+        3 => Weekday::Thursday,
+        4 => Weekday::Friday,
+        5 => Weekday::Saturday,
+        _ => Weekday::Sunday,
+    }
+}
+
+// Occurrence start times of an event overlapping [from, to], at most `limit` of them
+pub fn occurrence_starts(event: &Event, from: u64, to: u64, limit: usize) -> Vec<u64> {
+    let (Some(starts_at), Some(ends_at)) = (event.starts_at, event.ends_at) else {
+        return Vec::new();
+    };
+    let duration = ends_at.saturating_sub(starts_at);
+    match &event.recurrence {
+        None => {
+            if starts_at <= to && ends_at >= from {
+                vec![starts_at]
+            } else {
+                Vec::new()
+            }
+        }
+        Some(rule) => Starts::new(rule, starts_at)
+            .take_while(|start| *start <= to)
+            .filter(|start| start.saturating_add(duration) >= from)
+            .filter(|start| !rule.exceptions.contains(start))
+            .take(limit)
+            .collect(),
+    }
+}
+
+// Whether `start` is the start time of a (non-cancelled) occurrence of a recurring rule
+pub fn is_occurrence(rule: &RecurrenceRule, first: u64, start: u64) -> bool {
+    !rule.exceptions.contains(&start)
+        && Starts::new(rule, first)
+            .take_while(|candidate| *candidate <= start)
+            .any(|candidate| candidate == start)
+}
+
+// Index of the period `until` falls in
+fn period_of(rule: &RecurrenceRule, first: u64, until: u64) -> u64 {
+    let first_day = first / NANOS_PER_DAY;
+    let until_day = (until / NANOS_PER_DAY).max(first_day);
+    let periods = match rule.frequency {
+        Frequency::Daily => until_day - first_day,
+        Frequency::Weekly => (until_day - first_day + (first_day + 3) % 7) / 7,
+        Frequency::Monthly => {
+            let (first_year, first_month, _) = civil_from_days(first_day as i64);
+            let (until_year, until_month, _) = civil_from_days(until_day as i64);
+            ((until_year - first_year) * 12 + until_month as i64 - first_month as i64) as u64
+        }
+    };
+    periods / rule.interval()
+}
+
+// Start of the last occurrence of a rule that ends at `until` rather than after a
+// count. Seeks to the period holding `until` and steps back over periods without
+// an occurrence (monthly rules skipping short months) instead of walking the series.
+fn last_start_until(rule: &RecurrenceRule, first: u64, until: u64) -> Option<u64> {
+    let last_period = period_of(rule, first, until).min(MAX_PERIODS - 1);
+    (0..=last_period).rev().find_map(|period| {
+        Starts::in_period(rule, first, period)
+            .into_iter()
+            .find(|start| *start <= until)
+    })
+}
+
+// Start of the earliest occurrence of an event still running or yet to start at `at`;
+// None for unscheduled events and once the schedule is over. Only series bounded by
+// a count are walked from their first start; the others seek to the period `at`
+// falls in.
+pub fn current_or_next(event: &Event, at: u64) -> Option<u64> {
+    let (starts_at, ends_at) = (event.starts_at?, event.ends_at?);
+    let duration = ends_at.saturating_sub(starts_at);
+    let running = |start: &u64| start.saturating_add(duration) > at;
+    let Some(rule) = &event.recurrence else {
+        return Some(starts_at).filter(running);
+    };
+    let mut starts: Box<dyn Iterator<Item = u64>> = match rule.count {
+        // At most `MAX_COUNT` starts to walk
+        Some(_) => Box::new(Starts::new(rule, starts_at)),
+        None => {
+            let first_period = period_of(rule, starts_at, at.saturating_sub(duration));
+            Box::new(
+                (first_period..MAX_PERIODS)
+                    .flat_map(|period| Starts::in_period(rule, starts_at, period).into_iter().rev())
+                    .take_while(|start| rule.until.is_none_or(|until| *start <= until)),
+            )
+        }
+    };
+    starts.find(|start| running(start) && !rule.exceptions.contains(start))
+}
+
+// End of the last occurrence of an event; `u64::MAX` for open-ended series
+pub fn schedule_end(event: &Event) -> Option<u64> {
+    let (starts_at, ends_at) = (event.starts_at?, event.ends_at?);
+    let Some(rule) = &event.recurrence else {
+        return Some(ends_at);
+    };
+    let last = match (rule.count, rule.until) {
+        (None, None) => return Some(u64::MAX),
+        (None, Some(until)) => last_start_until(rule, starts_at, until),
+        // At most `MAX_COUNT` starts to walk
+        (Some(_), _) => Starts::new(rule, starts_at).last(),
+    };
+    let duration = ends_at.saturating_sub(starts_at);
+    Some(last.unwrap_or(starts_at).saturating_add(duration))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NANOS_PER_HOUR: u64 = 3_600 * 1_000_000_000;
+
+    // Time of `hour`:00 UTC on the given date
+    fn at(year: i64, month: u32, day: u32, hour: u64) -> u64 {
+        days_from_civil(year, month, day) as u64 * NANOS_PER_DAY + hour * NANOS_PER_HOUR
+    }
+
+    fn rule(frequency: Frequency) -> RecurrenceRule {
+        RecurrenceRule {
+            frequency,
+            interval: None,
+            by_day: Vec::new(),
+            count: None,
+            until: None,
+            exceptions: Vec::new(),
+        }
+    }
+
+    // A one-hour event starting at `starts_at`
+    fn event(starts_at: u64, recurrence: Option<RecurrenceRule>) -> Event {
+        Event {
+            starts_at: Some(starts_at),
+            ends_at: Some(starts_at + NANOS_PER_HOUR),
+            time_zone: Some("UTC".to_string()),
+            recurrence,
+            ..crate::test_event(1)
+        }
+    }
+
+    fn starts(rule: RecurrenceRule, first: u64) -> Vec<u64> {
+        occurrence_starts(&event(first, Some(rule)), 0, u64::MAX, usize::MAX)
+    }
+
+    #[test]
+    fn civil_dates_match_known_days() {
+        assert_eq!(civil_from_days(0), (1970, 1, 1));
+        assert_eq!(civil_from_days(-1), (1969, 12, 31));
+        assert_eq!(civil_from_days(11_016), (2000, 2, 29));
+        assert_eq!(civil_from_days(19_723), (2024, 1, 1));
+        assert_eq!(civil_from_days(-135_080), (1600, 3, 1));
+        assert_eq!(days_from_civil(2024, 1, 1), 19_723);
+    }
+
+    #[test]
+    fn civil_dates_round_trip() {
+        for days in -200_000..200_000 {
+            let (year, month, day) = civil_from_days(days);
+            assert_eq!(days_from_civil(year, month, day), days);
+        }
+    }
+
+    #[test]
+    fn month_lengths_follow_leap_years() {
+        assert_eq!(days_in_month(2023, 2), 28);
+        assert_eq!(days_in_month(2024, 2), 29);
+        assert_eq!(days_in_month(1900, 2), 28);
+        assert_eq!(days_in_month(2000, 2), 29);
+        assert_eq!(days_in_month(2024, 4), 30);
+        assert_eq!(days_in_month(2024, 12), 31);
+    }
+
+    #[test]
+    fn weekly_rule_walks_its_weekdays_from_the_first_start() {
+        // 2024-01-03 is a Wednesday; the Monday of that week comes before the first start
+        let rule = RecurrenceRule {
+            by_day: vec![Weekday::Friday, Weekday::Monday, Weekday::Wednesday],
+            count: Some(5),
+            ..rule(Frequency::Weekly)
+        };
+        assert_eq!(
+            starts(rule, at(2024, 1, 3, 10)),
+            vec![
+                at(2024, 1, 3, 10),
+                at(2024, 1, 5, 10),
+                at(2024, 1, 8, 10),
+                at(2024, 1, 10, 10),
+                at(2024, 1, 12, 10),
+            ]
+        );
+    }
+
+    #[test]
+    fn weekly_rule_skips_weeks_by_interval() {
+        let rule = RecurrenceRule {
+            interval: Some(2),
+            count: Some(3),
+            ..rule(Frequency::Weekly)
+        };
+        assert_eq!(
+            starts(rule, at(2024, 1, 3, 10)),
+            vec![at(2024, 1, 3, 10), at(2024, 1, 17, 10), at(2024, 1, 31, 10)]
+        );
+    }
+
+    #[test]
+    fn weekly_rule_starting_in_the_first_week_of_1970() {
+        // 1970-01-01 is a Thursday, in a week that started before the epoch
+        let rule = RecurrenceRule {
+            by_day: vec![Weekday::Monday, Weekday::Thursday],
+            count: Some(3),
+            ..rule(Frequency::Weekly)
+        };
+        let first = at(1970, 1, 1, 0);
+        assert_eq!(starts(rule.clone(), first), vec![first, at(1970, 1, 5, 0), at(1970, 1, 8, 0)]);
+
+        let until = RecurrenceRule {
+            count: None,
+            until: Some(at(1970, 1, 20, 0)),
+            ..rule
+        };
+        assert_eq!(last_start_until(&until, first, at(1970, 1, 20, 0)), Some(at(1970, 1, 19, 0)));
+        assert_eq!(
+            last_start_until(&until, first, at(1970, 1, 20, 0)),
+            Starts::new(&until, first).last()
+        );
+    }
+
+    #[test]
+    fn monthly_rule_skips_months_without_the_day() {
+        let rule = RecurrenceRule {
+            count: Some(4),
+            ..rule(Frequency::Monthly)
+        };
+        assert_eq!(
+            starts(rule, at(2024, 1, 31, 9)),
+            vec![at(2024, 1, 31, 9), at(2024, 3, 31, 9), at(2024, 5, 31, 9), at(2024, 7, 31, 9)]
+        );
+    }
+
+    #[test]
+    fn monthly_rule_crosses_years() {
+        let rule = RecurrenceRule {
+            interval: Some(5),
+            count: Some(3),
+            ..rule(Frequency::Monthly)
+        };
+        assert_eq!(
+            starts(rule, at(2024, 10, 15, 9)),
+            vec![at(2024, 10, 15, 9), at(2025, 3, 15, 9), at(2025, 8, 15, 9)]
+        );
+    }
+
+    #[test]
+    fn exceptions_are_skipped_but_count() {
+        let rule = RecurrenceRule {
+            count: Some(3),
+            exceptions: vec![at(2024, 1, 2, 8)],
+            ..rule(Frequency::Daily)
+        };
+        assert_eq!(starts(rule, at(2024, 1, 1, 8)), vec![at(2024, 1, 1, 8), at(2024, 1, 3, 8)]);
+    }
+
+    #[test]
+    fn until_is_inclusive() {
+        let rule = RecurrenceRule {
+            until: Some(at(2024, 1, 3, 8)),
+            ..rule(Frequency::Daily)
+        };
+        assert_eq!(
+            starts(rule, at(2024, 1, 1, 8)),
+            vec![at(2024, 1, 1, 8), at(2024, 1, 2, 8), at(2024, 1, 3, 8)]
+        );
+    }
+
+    #[test]
+    fn is_occurrence_matches_generated_starts() {
+        let rule = RecurrenceRule {
+            by_day: vec![Weekday::Tuesday, Weekday::Thursday],
+            exceptions: vec![at(2024, 1, 11, 18)],
+            ..rule(Frequency::Weekly)
+        };
+        let first = at(2024, 1, 2, 18);
+        assert!(is_occurrence(&rule, first, at(2024, 1, 4, 18)));
+        assert!(is_occurrence(&rule, first, at(2024, 2, 27, 18)));
+        assert!(!is_occurrence(&rule, first, at(2024, 1, 11, 18)));
+        assert!(!is_occurrence(&rule, first, at(2024, 1, 3, 18)));
+        assert!(!is_occurrence(&rule, first, at(2024, 1, 4, 19)));
+    }
+
+    #[test]
+    fn schedule_end_of_one_off_and_open_ended_events() {
+        let start = at(2024, 1, 1, 8);
+        assert_eq!(schedule_end(&event(start, None)), Some(start + NANOS_PER_HOUR));
+        assert_eq!(schedule_end(&event(start, Some(rule(Frequency::Daily)))), Some(u64::MAX));
+        let counted = RecurrenceRule {
+            count: Some(10),
+            ..rule(Frequency::Daily)
+        };
+        assert_eq!(
+            schedule_end(&event(start, Some(counted))),
+            Some(at(2024, 1, 10, 8) + NANOS_PER_HOUR)
+        );
+    }
+
+    // Seeking to the last period must agree with walking the whole series
+    #[test]
+    fn last_start_until_matches_a_full_walk() {
+        let every_day = vec![
+            Weekday::Monday,
+            Weekday::Tuesday,
+            Weekday::Wednesday,
+            Weekday::Thursday,
+            Weekday::Friday,
+            Weekday::Saturday,
+            Weekday::Sunday,
+        ];
+        let cases = [
+            (rule(Frequency::Daily), at(2024, 1, 1, 8)),
+            (RecurrenceRule { interval: Some(3), ..rule(Frequency::Daily) }, at(2024, 1, 1, 23)),
+            (rule(Frequency::Weekly), at(2024, 1, 3, 10)),
+            (
+                RecurrenceRule { by_day: every_day, ..rule(Frequency::Weekly) },
+                at(2024, 1, 3, 10),
+            ),
+            (
+                RecurrenceRule {
+                    interval: Some(3),
+                    by_day: vec![Weekday::Monday, Weekday::Saturday],
+                    ..rule(Frequency::Weekly)
+                },
+                at(2024, 1, 6, 10),
+            ),
+            (rule(Frequency::Monthly), at(2024, 1, 31, 9)),
+            (RecurrenceRule { interval: Some(5), ..rule(Frequency::Monthly) }, at(2024, 1, 31, 9)),
+            (RecurrenceRule { interval: Some(12), ..rule(Frequency::Monthly) }, at(2024, 2, 29, 9)),
+        ];
+        let untils = [
+            at(2024, 1, 1, 8),
+            at(2024, 2, 29, 8),
+            at(2024, 2, 29, 10),
+            at(2025, 6, 30, 0),
+            at(2030, 12, 31, 23),
+            at(2101, 3, 1, 0),
+            at(2400, 1, 1, 0),
+        ];
+        for (rule, first) in cases {
+            for until in untils.into_iter().filter(|until| *until >= first) {
+                let rule = RecurrenceRule { until: Some(until), ..rule.clone() };
+                assert_eq!(
+                    last_start_until(&rule, first, until),
+                    Starts::new(&rule, first).last(),
+                    "series starting {} until {}",
+                    first,
+                    until
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn validate_rejects_costly_or_inconsistent_rules() {
+        let start = at(2024, 1, 1, 8);
+        assert!(validate(&rule(Frequency::Daily), start).is_ok());
+        let both = RecurrenceRule {
+            count: Some(3),
+            until: Some(start + NANOS_PER_DAY),
+            ..rule(Frequency::Daily)
+        };
+        assert!(validate(&both, start).is_err());
+        let early = RecurrenceRule {
+            until: Some(start - 1),
+            ..rule(Frequency::Daily)
+        };
+        assert!(validate(&early, start).is_err());
+        let too_many = RecurrenceRule {
+            count: Some(MAX_COUNT + 1),
+            ..rule(Frequency::Daily)
+        };
+        assert!(validate(&too_many, start).is_err());
+        let monthly_days = RecurrenceRule {
+            by_day: vec![Weekday::Monday],
+            ..rule(Frequency::Monthly)
+        };
+        assert!(validate(&monthly_days, start).is_err());
+    }
+
+    #[test]
+    fn finds_the_current_or_next_occurrence() {
+        let first = at(2024, 1, 1, 10);
+        let mut daily = rule(Frequency::Daily);
+        daily.exceptions = vec![at(2024, 3, 6, 10)];
+        let series = event(first, Some(daily.clone()));
+        let next = |at| current_or_next(&series, at);
+        assert_eq!(next(0), Some(first));
+        assert_eq!(next(at(2024, 3, 5, 10) + 1), Some(at(2024, 3, 5, 10)));
+        // The exception is skipped
+        assert_eq!(next(at(2024, 3, 5, 11)), Some(at(2024, 3, 7, 10)));
+
+        daily.until = Some(at(2024, 3, 5, 10));
+        let bounded = event(first, Some(daily.clone()));
+        assert_eq!(current_or_next(&bounded, at(2024, 3, 5, 10)), Some(at(2024, 3, 5, 10)));
+        assert_eq!(current_or_next(&bounded, at(2024, 3, 5, 11)), None);
+
+        daily.until = None;
+        daily.count = Some(3);
+        let counted = event(first, Some(daily));
+        assert_eq!(current_or_next(&counted, at(2024, 1, 2, 11)), Some(at(2024, 1, 3, 10)));
+        assert_eq!(current_or_next(&counted, at(2024, 1, 3, 11)), None);
+
+        let once = event(first, None);
+        assert_eq!(current_or_next(&once, first + NANOS_PER_HOUR - 1), Some(first));
+        assert_eq!(current_or_next(&once, first + NANOS_PER_HOUR), None);
+    }
+
+    #[test]
+    fn seeks_weekly_and_monthly_series() {
+        // Mondays and Wednesdays from Wednesday 2024-01-03
+        let mut weekly = rule(Frequency::Weekly);
+        weekly.by_day = vec![Weekday::Monday, Weekday::Wednesday];
+        let series = event(at(2024, 1, 3, 10), Some(weekly));
+        assert_eq!(current_or_next(&series, at(2024, 6, 4, 0)), Some(at(2024, 6, 5, 10)));
+        assert_eq!(current_or_next(&series, at(2024, 6, 6, 0)), Some(at(2024, 6, 10, 10)));
+
+        // The 31st of every month skips the shorter months
+        let series = event(at(2024, 1, 31, 10), Some(rule(Frequency::Monthly)));
+        assert_eq!(current_or_next(&series, at(2024, 4, 1, 0)), Some(at(2024, 5, 31, 10)));
+    }
+}
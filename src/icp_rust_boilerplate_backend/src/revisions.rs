@@ -0,0 +1,161 @@
+// Revision history of events.
+//
+// Before update_event, patch_event or rollback_event overwrite an event, its
+// current state is stored in `REVISIONS` under `(event id, version)`, so a
+// revision is numbered by the `version` it had. Versions also move on ownership
+// changes and restores, which store no revision, so the numbers of an event's
+// revisions can have gaps. Only the newest `MAX_REVISIONS` are kept per event.
+//
+// Revisions follow the event into the trash and are dropped when it is purged
+// or force-deleted.
+//
+// The event of a revision is stored in the versioned envelope of `STORAGE`
+// (see `migrations`), so old revisions keep decoding as `Event` changes.
+
+use candid::{CandidType, Decode, Encode, Principal};
+use ic_stable_structures::{BoundedStorable, Storable};
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+
+use crate::audit::{self, FieldChange};
+use crate::{migrations, Error, Event, REVISIONS};
+
+// Revisions kept per event; older ones are dropped as new ones are stored
+const MAX_REVISIONS: usize = 20;
+
+// Fields an edit can change; the diffs of the history only cover these
+const EDITABLE_FIELDS: [&str; 9] = [
+    "event_title",
+    "event_description",
+    "event_location",
+    "event_card_imgurl",
+    "starts_at",
+    "ends_at",
+    "time_zone",
+    "recurrence",
+    "capacity",
+];
+
+// A stored state of an event and the edit that replaced it
+#[derive(CandidType, Clone, Serialize, Deserialize)]
+pub struct Revision {
+    pub revision: u64,
+    pub event: Event,
+    pub replaced_by: Principal,
+    pub replaced_at: u64,
+}
+
+// Entry of get_event_history: a revision and what the edit replacing it changed
+#[derive(CandidType, Serialize, Deserialize)]
+pub struct RevisionSummary {
+    pub revision: u64,
+    pub replaced_by: Principal,
+    pub replaced_at: u64,
+    pub changes: Vec<FieldChange>,
+}
+
+// Stored form of a revision, holding the event as an enveloped record
+#[derive(CandidType, Deserialize)]
+struct StoredRevision {
+    revision: u64,
+    event: Vec<u8>,
+    replaced_by: Principal,
+    replaced_at: u64,
+}
+
+impl Storable for Revision {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        let stored = StoredRevision {
+            revision: self.revision,
+            event: migrations::encode_event(&self.event),
+            replaced_by: self.replaced_by,
+            replaced_at: self.replaced_at,
+        };
+        Cow::Owned(Encode!(&stored).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        match Decode!(bytes.as_ref(), StoredRevision) {
+            Ok(stored) => Self {
+                revision: stored.revision,
+                event: migrations::decode_event(&stored.event)
+                    .unwrap_or_else(|msg| ic_cdk::trap(&msg)),
+                replaced_by: stored.replaced_by,
+                replaced_at: stored.replaced_at,
+            },
+            // Schema v11 stored the event as bare candid; the v12 migration rewrites those
+            Err(_) => Decode!(bytes.as_ref(), Self).unwrap(),
+        }
+    }
+}
+
+impl BoundedStorable for Revision {
+    // An enveloped event plus a principal and two numbers
+    const MAX_SIZE: u32 = Event::MAX_SIZE + 128;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+fn event_range(id: u64) -> std::ops::RangeInclusive<(u64, u64)> {
+    (id, 0)..=(id, u64::MAX)
+}
+
+// Stores `event` as the revision replaced by an edit of `principal`
+pub fn store(event: &Event, principal: Principal, now: u64) {
+    let revision = Revision {
+        revision: event.version,
+        event: event.clone(),
+        replaced_by: principal,
+        replaced_at: now,
+    };
+    REVISIONS.with(|r| {
+        let mut r = r.borrow_mut();
+        r.insert((event.id, event.version), revision);
+        let keys: Vec<(u64, u64)> = r.range(event_range(event.id)).map(|(key, _)| key).collect();
+        for key in keys.iter().take(keys.len().saturating_sub(MAX_REVISIONS)) {
+            r.remove(key);
+        }
+    });
+}
+
+// The stored revision `revision` of event `id`
+pub fn get(id: u64, revision: u64) -> Result<Revision, Error> {
+    REVISIONS
+        .with(|r| r.borrow().get(&(id, revision)))
+        .ok_or_else(|| Error::NotFound {
+            msg: format!("Event with id={} has no revision {}", id, revision),
+        })
+}
+
+// The stored revisions of `current` newest first, each with the changes made by
+// the edit that replaced it
+pub fn history(current: &Event) -> Vec<RevisionSummary> {
+    let revisions: Vec<Revision> =
+        REVISIONS.with(|r| r.borrow().range(event_range(current.id)).map(|(_, rev)| rev).collect());
+    let next_states = revisions.iter().map(|rev| &rev.event).skip(1).chain([current]);
+    let mut summaries: Vec<RevisionSummary> = revisions
+        .iter()
+        .zip(next_states)
+        .map(|(rev, next)| RevisionSummary {
+            revision: rev.revision,
+            replaced_by: rev.replaced_by,
+            replaced_at: rev.replaced_at,
+            changes: audit::diff(Some(&rev.event), Some(next))
+                .into_iter()
+                .filter(|change| EDITABLE_FIELDS.contains(&change.field.as_str()))
+                .collect(),
+        })
+        .collect();
+    summaries.reverse();
+    summaries
+}
+
+// Removes every revision of an event
+pub fn remove_event(id: u64) {
+    REVISIONS.with(|r| {
+        let keys: Vec<(u64, u64)> = r.borrow().range(event_range(id)).map(|(key, _)| key).collect();
+        let mut r = r.borrow_mut();
+        for key in keys {
+            r.remove(&key);
+        }
+    });
+}
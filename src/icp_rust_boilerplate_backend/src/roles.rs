@@ -0,0 +1,145 @@
+// Per-event roles.
+//
+// The creator of an event holds the `Owner` role through `Event.owner`; every
+// other role is granted explicitly and stored in `ROLES` under
+// `(event id, principal)`. A principal holds at most one role per event.
+// What each role may do is decided by the permission matrix in `guard`.
+
+use candid::{CandidType, Principal};
+use ic_stable_structures::{BoundedStorable, Storable};
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::ops::Bound;
+
+use crate::{Event, StorablePrincipal, ROLES};
+
+// (event id, principal)
+pub type RoleKey = (u64, StorablePrincipal);
+
+// Ordered from most to least privileged
+#[derive(CandidType, Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Role {
+    Owner,
+    CoHost,
+    Moderator,
+    CheckInStaff,
+}
+
+// A role held by a principal, as returned by list_roles
+#[derive(CandidType, Serialize, Deserialize)]
+pub struct RoleAssignment {
+    pub principal: Principal,
+    pub role: Role,
+    // When the role was granted; the owner's is the event's creation time
+    pub granted_at: u64,
+}
+
+// A stored grant
+#[derive(Clone, Copy)]
+pub struct Grant {
+    pub role: Role,
+    pub granted_at: u64,
+}
+
+impl Role {
+    fn to_byte(self) -> u8 {
+        self as u8
+    }
+
+    fn from_byte(byte: u8) -> Self {
+        match byte {
+            0 => Self::Owner,
+            1 => Self::CoHost,
+            2 => Self::Moderator,
+            _ => Self::CheckInStaff,
+        }
+    }
+}
+
+// Stored as | role (1 byte) | granted_at (u64 BE) |
+impl Storable for Grant {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        let mut bytes = Vec::with_capacity(Self::MAX_SIZE as usize);
+        bytes.push(self.role.to_byte());
+        bytes.extend_from_slice(&self.granted_at.to_be_bytes());
+        Cow::Owned(bytes)
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Self {
+            role: Role::from_byte(bytes[0]),
+            granted_at: u64::from_be_bytes(bytes[1..9].try_into().unwrap()),
+        }
+    }
+}
+
+impl BoundedStorable for Grant {
+    const MAX_SIZE: u32 = 9;
+    const IS_FIXED_SIZE: bool = true;
+}
+
+// Key range covering every grant of an event
+fn event_range(id: u64) -> (Bound<RoleKey>, Bound<RoleKey>) {
+    let first = StorablePrincipal(Principal::from_slice(&[]));
+    match id.checked_add(1) {
+        Some(next) => (Bound::Included((id, first)), Bound::Excluded((next, first))),
+        None => (Bound::Included((id, first)), Bound::Unbounded),
+    }
+}
+
+// The role `principal` holds on `event`, if any
+pub fn role_of(event: &Event, principal: Principal) -> Option<Role> {
+    if event.owner == principal {
+        return Some(Role::Owner);
+    }
+    ROLES.with(|r| r.borrow().get(&(event.id, StorablePrincipal(principal)))).map(|grant| grant.role)
+}
+
+// Gives `principal` a role on event `id`, replacing the one they held
+pub fn grant(id: u64, principal: Principal, role: Role, now: u64) {
+    ROLES.with(|r| {
+        r.borrow_mut().insert(
+            (id, StorablePrincipal(principal)),
+            Grant {
+                role,
+                granted_at: now,
+            },
+        )
+    });
+}
+
+// Takes away the role `principal` holds on event `id`; returns false if they held none
+pub fn revoke(id: u64, principal: Principal) -> bool {
+    ROLES.with(|r| r.borrow_mut().remove(&(id, StorablePrincipal(principal)))).is_some()
+}
+
+// Every role held on `event`, the owner first
+pub fn list(event: &Event) -> Vec<RoleAssignment> {
+    let owner = RoleAssignment {
+        principal: event.owner,
+        role: Role::Owner,
+        granted_at: event.created_at,
+    };
+    let granted: Vec<RoleAssignment> = ROLES.with(|r| {
+        r.borrow()
+            .range(event_range(event.id))
+            .map(|((_, principal), grant)| RoleAssignment {
+                principal: principal.0,
+                role: grant.role,
+                granted_at: grant.granted_at,
+            })
+            .collect()
+    });
+    std::iter::once(owner).chain(granted).collect()
+}
+
+// Removes every grant of an event
+pub fn remove_event(id: u64) {
+    ROLES.with(|r| {
+        let keys: Vec<RoleKey> = r.borrow().range(event_range(id)).map(|(k, _)| k).collect();
+        let mut r = r.borrow_mut();
+        for key in keys {
+            r.remove(&key);
+        }
+    });
+}
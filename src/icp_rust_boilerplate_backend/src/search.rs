@@ -0,0 +1,295 @@
+// Full-text search over event titles, descriptions and locations.
+//
+// `SEARCH_INDEX` is an inverted index keyed by `(token, event id)` holding the
+// token's weight in that event. Title matches weigh more than location matches,
+// which weigh more than description matches. Query terms are matched as
+// prefixes, every term has to match, and hits are ranked by their summed weight.
+
+use candid::CandidType;
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::ops::Bound;
+
+use ic_stable_structures::{BoundedStorable, Storable};
+
+use crate::{Event, SEARCH_INDEX, STORAGE};
+
+// Longest token kept in the index, in bytes; longer words are truncated
+const MAX_TOKEN_LEN: usize = 32;
+
+// Tokens shorter than this are not indexed
+const MIN_TOKEN_LEN: usize = 2;
+
+// Most query terms taken into account
+const MAX_QUERY_TERMS: usize = 8;
+
+// Most index entries read per query term; a search reaching it reports itself truncated
+const MAX_POSTINGS_PER_TERM: usize = 5_000;
+
+// Largest page size served by search_events
+const MAX_PAGE_SIZE: u32 = 50;
+
+const TITLE_WEIGHT: u32 = 5;
+const LOCATION_WEIGHT: u32 = 3;
+const DESCRIPTION_WEIGHT: u32 = 1;
+
+// Normalized word stored in the inverted index
+#[derive(Clone, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Token(String);
+
+impl Storable for Token {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Borrowed(self.0.as_bytes())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Self(String::from_utf8(bytes.into_owned()).unwrap())
+    }
+}
+
+impl BoundedStorable for Token {
+    const MAX_SIZE: u32 = MAX_TOKEN_LEN as u32;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+// A ranked search result
+#[derive(CandidType, Serialize, Deserialize)]
+pub struct SearchHit {
+    pub event: Event,
+    pub score: u32,
+}
+
+// Page of results returned by search_events
+#[derive(CandidType, Serialize, Deserialize)]
+pub struct SearchResponse {
+    pub hits: Vec<SearchHit>,
+    // Offset of the next page in the ranked result list
+    pub next_cursor: Option<u64>,
+    // Whether a query term matched more index entries than were read, so some
+    // matching events may be missing; a longer or narrower query helps
+    pub truncated: bool,
+}
+
+// Splits text into lowercase alphanumeric tokens
+fn tokenize(text: &str) -> impl Iterator<Item = String> + '_ {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| word.chars().count() >= MIN_TOKEN_LEN)
+        .map(|word| truncate(word.to_lowercase()))
+}
+
+// Cuts a token down to MAX_TOKEN_LEN bytes on a character boundary
+fn truncate(mut token: String) -> String {
+    if token.len() > MAX_TOKEN_LEN {
+        let mut end = MAX_TOKEN_LEN;
+        while !token.is_char_boundary(end) {
+            end -= 1;
+        }
+        token.truncate(end);
+    }
+    token
+}
+
+// Weight of every token appearing in the searchable fields of an event
+fn weighted_tokens(event: &Event) -> BTreeMap<String, u32> {
+    let mut tokens = BTreeMap::new();
+    // Hidden events stay out of the index until they are unhidden
+    if event.hidden_at.is_some() {
+        return tokens;
+    }
+    for (text, weight) in [
+        (&event.event_title, TITLE_WEIGHT),
+        (&event.event_location, LOCATION_WEIGHT),
+        (&event.event_description, DESCRIPTION_WEIGHT),
+    ] {
+        for token in tokenize(text) {
+            let entry = tokens.entry(token).or_insert(0u32);
+            *entry = entry.saturating_add(weight);
+        }
+    }
+    tokens
+}
+
+// Keeps the inverted index in step with a write to `STORAGE`
+pub fn reindex(old: Option<&Event>, new: Option<&Event>) {
+    let old_tokens = old.map(weighted_tokens).unwrap_or_default();
+    let new_tokens = new.map(weighted_tokens).unwrap_or_default();
+
+    SEARCH_INDEX.with(|index| {
+        let mut index = index.borrow_mut();
+        if let Some(old) = old {
+            for token in old_tokens.keys() {
+                if !new_tokens.contains_key(token) {
+                    index.remove(&(Token(token.clone()), old.id));
+                }
+            }
+        }
+        if let Some(new) = new {
+            for (token, weight) in new_tokens {
+                index.insert((Token(token), new.id), weight);
+            }
+        }
+    });
+}
+
+// Scores of events containing a token that starts with `prefix`, and whether
+// more than MAX_POSTINGS_PER_TERM entries matched and the rest were left unread
+fn prefix_scores(prefix: &str) -> (BTreeMap<u64, u32>, bool) {
+    let mut scores = BTreeMap::new();
+    let mut truncated = false;
+    SEARCH_INDEX.with(|index| {
+        let index = index.borrow();
+        let start = Bound::Included((Token(prefix.to_string()), 0));
+        for (read, ((token, id), weight)) in index.range((start, Bound::Unbounded)).enumerate() {
+            if !token.0.starts_with(prefix) {
+                break;
+            }
+            if read == MAX_POSTINGS_PER_TERM {
+                truncated = true;
+                break;
+            }
+            let score: &mut u32 = scores.entry(id).or_insert(0);
+            *score = score.saturating_add(weight);
+        }
+    });
+    (scores, truncated)
+}
+
+// Runs a ranked prefix search and returns the page starting at `cursor`
+pub fn search(query: &str, limit: u32, cursor: Option<u64>) -> SearchResponse {
+    let mut terms: Vec<String> = tokenize(query).collect();
+    terms.sort();
+    terms.dedup();
+    terms.truncate(MAX_QUERY_TERMS);
+    if terms.is_empty() {
+        return SearchResponse {
+            hits: Vec::new(),
+            next_cursor: None,
+            truncated: false,
+        };
+    }
+
+    // Every term has to match; scores add up across terms
+    let mut ranked: Option<BTreeMap<u64, u32>> = None;
+    let mut truncated = false;
+    for term in &terms {
+        let (term_scores, term_truncated) = prefix_scores(term);
+        truncated |= term_truncated;
+        ranked = Some(match ranked {
+            None => term_scores,
+            Some(scores) => scores
+                .into_iter()
+                .filter_map(|(id, score)| {
+                    term_scores
+                        .get(&id)
+                        .map(|term_score| (id, score.saturating_add(*term_score)))
+                })
+                .collect(),
+        });
+    }
+
+    let mut ranked: Vec<(u64, u32)> = ranked.unwrap_or_default().into_iter().collect();
+    ranked.sort_by(|(a_id, a_score), (b_id, b_score)| b_score.cmp(a_score).then(a_id.cmp(b_id)));
+
+    let offset = cursor.unwrap_or(0) as usize;
+    let limit = limit.clamp(1, MAX_PAGE_SIZE) as usize;
+    let hits: Vec<SearchHit> = ranked
+        .iter()
+        .skip(offset)
+        .take(limit)
+        .filter_map(|(id, score)| {
+            STORAGE
+                .with(|s| s.borrow().get(id))
+                .map(|event| SearchHit {
+                    event,
+                    score: *score,
+                })
+        })
+        .collect();
+
+    let next = offset + limit;
+    SearchResponse {
+        hits,
+        next_cursor: (next < ranked.len()).then_some(next as u64),
+        truncated,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tokens(text: &str) -> Vec<String> {
+        tokenize(text).collect()
+    }
+
+    fn store(event: Event) {
+        reindex(None, Some(&event));
+        STORAGE.with(|s| s.borrow_mut().insert(event.id, event));
+    }
+
+    fn ids(response: &SearchResponse) -> Vec<u64> {
+        response.hits.iter().map(|hit| hit.event.id).collect()
+    }
+
+    #[test]
+    fn tokenizes_lowercase_alphanumeric_words() {
+        assert_eq!(tokens("Rust-Meetup: Zürich, 2024!"), ["rust", "meetup", "zürich", "2024"]);
+        assert_eq!(tokens("a I of"), ["of"]);
+        assert!(tokens("  ,.;  ").is_empty());
+    }
+
+    #[test]
+    fn truncates_long_tokens_on_a_character_boundary() {
+        assert_eq!(tokens(&"x".repeat(40)), ["x".repeat(MAX_TOKEN_LEN)]);
+        // 'é' is two bytes wide, so cutting at 32 bytes would split the 16th
+        let accented = tokens(&format!("a{}", "é".repeat(20)));
+        assert_eq!(accented, [format!("a{}", "é".repeat(15))]);
+    }
+
+    #[test]
+    fn matches_prefixes_and_ranks_by_weight() {
+        store(Event {
+            event_title: "Board games".to_string(),
+            ..crate::test_event(1)
+        });
+        store(Event {
+            event_description: "Bring your board".to_string(),
+            ..crate::test_event(2)
+        });
+        store(Event {
+            event_location: "Boardwalk".to_string(),
+            event_title: "Games night".to_string(),
+            ..crate::test_event(3)
+        });
+
+        let response = search("boa", 10, None);
+        assert_eq!(ids(&response), [1, 3, 2]);
+        assert!(!response.truncated);
+        // Every term has to match
+        assert_eq!(ids(&search("board GAMES", 10, None)), [1, 3]);
+        assert!(search("boards", 10, None).hits.is_empty());
+        assert!(search("?", 10, None).hits.is_empty());
+
+        let first = search("boa", 2, None);
+        assert_eq!((ids(&first), first.next_cursor), (vec![1, 3], Some(2)));
+        let second = search("boa", 2, first.next_cursor);
+        assert_eq!((ids(&second), second.next_cursor), (vec![2], None));
+    }
+
+    #[test]
+    fn reports_terms_matching_too_many_entries() {
+        SEARCH_INDEX.with(|index| {
+            let mut index = index.borrow_mut();
+            for id in 0..=MAX_POSTINGS_PER_TERM as u64 {
+                index.insert((Token("popular".to_string()), id), 1);
+            }
+        });
+        let (scores, truncated) = prefix_scores("pop");
+        assert_eq!((scores.len(), truncated), (MAX_POSTINGS_PER_TERM, true));
+        assert!(search("popular", 10, None).truncated);
+
+        SEARCH_INDEX.with(|index| index.borrow_mut().remove(&(Token("popular".to_string()), 0)));
+        assert!(!prefix_scores("pop").1);
+    }
+}
@@ -0,0 +1,168 @@
+// Soft deletion.
+//
+// delete_event moves an event out of `STORAGE` into `TRASH`, dropping it from
+// the listing and search indexes but keeping its attendees, RSVPs, roles and
+// revisions so restore_event can bring it back unchanged. `TRASH_EXPIRY` orders
+// trashed events by the time they are due to be purged and `TRASH_BY_OWNER` lets
+// owners list their trash.
+//
+// Purging is driven by a one-shot `ic-cdk-timers` timer, which is always set to
+// the earliest purge time in the trash. Timers do not survive upgrades, so the
+// timer is set again from `post_upgrade`.
+
+use candid::{CandidType, Principal};
+use ic_cdk::api::time;
+use ic_cdk_timers::TimerId;
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::ops::Bound;
+use std::time::Duration;
+
+use crate::{
+    attendance, revisions, roles, Error, Event, StorablePrincipal, TRASH, TRASH_BY_OWNER,
+    TRASH_EXPIRY,
+};
+
+// How long an event stays in the trash before it is purged: 30 days
+pub const TRASH_RETENTION: u64 = 30 * 24 * 60 * 60 * 1_000_000_000;
+
+// Most events purged per timer run; the timer fires again while more are due
+const PURGE_BATCH_SIZE: usize = 100;
+
+// Largest page size served by list_trash
+const MAX_TRASH_PAGE: usize = 100;
+
+thread_local! {
+    // Timer set for the next purge, if any
+    static PURGE_TIMER: RefCell<Option<TimerId>> = const { RefCell::new(None) };
+}
+
+// A trashed event and the time it will be purged
+#[derive(CandidType, Serialize, Deserialize)]
+pub struct TrashedEvent {
+    pub event: Event,
+    pub purge_at: u64,
+}
+
+// Page of the caller's trash returned by list_trash
+#[derive(CandidType, Serialize, Deserialize)]
+pub struct TrashPage {
+    pub events: Vec<TrashedEvent>,
+    // Event id to continue after
+    pub next_cursor: Option<u64>,
+}
+
+fn purge_at(event: &Event) -> u64 {
+    event
+        .deleted_at
+        .unwrap_or_default()
+        .saturating_add(TRASH_RETENTION)
+}
+
+// Puts an event removed from `STORAGE`, with `deleted_at` set, into the trash
+pub fn insert(event: Event) {
+    TRASH_EXPIRY.with(|e| e.borrow_mut().insert((purge_at(&event), event.id), ()));
+    TRASH_BY_OWNER.with(|o| o.borrow_mut().insert((StorablePrincipal(event.owner), event.id), ()));
+    TRASH.with(|t| t.borrow_mut().insert(event.id, event));
+    schedule_purge();
+}
+
+// Takes the event with the given id out of the trash if `principal` owns it
+pub fn take(id: u64, principal: Principal) -> Result<Event, Error> {
+    let event = TRASH
+        .with(|t| t.borrow().get(&id))
+        .ok_or_else(|| Error::NotFound {
+            msg: format!("Event with id={} is not in the trash", id),
+        })?;
+    if event.owner != principal {
+        return Err(Error::NotAuthorized {
+            msg: format!("You're not allowed to restore the event with id={}", id),
+            caller: principal,
+        });
+    }
+    remove(&event);
+    schedule_purge();
+    Ok(event)
+}
+
+fn remove(event: &Event) {
+    TRASH.with(|t| t.borrow_mut().remove(&event.id));
+    TRASH_EXPIRY.with(|e| e.borrow_mut().remove(&(purge_at(event), event.id)));
+    TRASH_BY_OWNER.with(|o| o.borrow_mut().remove(&(StorablePrincipal(event.owner), event.id)));
+}
+
+// Lists the trashed events owned by `owner` by id, starting after `cursor`
+pub fn page(owner: Principal, cursor: Option<u64>, limit: u32) -> TrashPage {
+    let start = match cursor {
+        Some(id) => Bound::Excluded((StorablePrincipal(owner), id)),
+        None => Bound::Included((StorablePrincipal(owner), 0)),
+    };
+    let limit = (limit as usize).clamp(1, MAX_TRASH_PAGE);
+    let ids: Vec<u64> = TRASH_BY_OWNER.with(|o| {
+        o.borrow()
+            .range((start, Bound::Included((StorablePrincipal(owner), u64::MAX))))
+            .take(limit + 1)
+            .map(|((_, id), _)| id)
+            .collect()
+    });
+
+    // An extra entry means there is at least one more page
+    let next_cursor = (ids.len() > limit).then(|| ids[limit - 1]);
+    let events = ids
+        .into_iter()
+        .take(limit)
+        .filter_map(|id| TRASH.with(|t| t.borrow().get(&id)))
+        .map(|event| TrashedEvent {
+            purge_at: purge_at(&event),
+            event,
+        })
+        .collect();
+    TrashPage {
+        events,
+        next_cursor,
+    }
+}
+
+// Permanently removes trashed events whose retention ran out, together with
+// their attendees, roles and revisions
+pub fn purge_expired(now: u64) {
+    let due: Vec<(u64, u64)> = TRASH_EXPIRY.with(|e| {
+        e.borrow()
+            .range(..=(now, u64::MAX))
+            .take(PURGE_BATCH_SIZE)
+            .map(|(key, _)| key)
+            .collect()
+    });
+    for (_, id) in due {
+        purge(id);
+    }
+}
+
+// Permanently removes the trashed event with the given id together with its
+// attendees, roles and revisions
+pub fn purge(id: u64) -> Option<Event> {
+    let event = TRASH.with(|t| t.borrow().get(&id))?;
+    remove(&event);
+    attendance::remove_event(id);
+    roles::remove_event(id);
+    revisions::remove_event(id);
+    Some(event)
+}
+
+// Sets the purge timer to the earliest purge time in the trash, or clears it
+// when the trash is empty
+pub fn schedule_purge() {
+    if let Some(timer) = PURGE_TIMER.with(|t| t.borrow_mut().take()) {
+        ic_cdk_timers::clear_timer(timer);
+    }
+    let next = TRASH_EXPIRY.with(|e| e.borrow().first_key_value().map(|(key, _)| key.0));
+    let Some(next) = next else {
+        return;
+    };
+    let timer = ic_cdk_timers::set_timer(Duration::from_nanos(next.saturating_sub(time())), || {
+        PURGE_TIMER.with(|t| t.borrow_mut().take());
+        purge_expired(time());
+        schedule_purge();
+    });
+    PURGE_TIMER.with(|t| *t.borrow_mut() = Some(timer));
+}
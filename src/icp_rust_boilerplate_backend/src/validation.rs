@@ -0,0 +1,349 @@
+// Validation and normalization of the text fields of an `EventPayload`.
+//
+// `validate` first normalizes the payload in place (trimming, and collapsing
+// whitespace runs in single-line fields), then checks every field against the
+// limits in `VALIDATION_LIMITS`. All offending fields are reported together in a
+// single `InvalidInput` error. Lengths count characters, not bytes.
+//
+// Admins can change the limits with set_validation_limits within the hard caps
+// below. Since a character takes up to four bytes, the text fields are also
+// capped together in bytes, so that they always fit in an event record.
+
+use candid::{CandidType, Decode, Encode};
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+
+use ic_stable_structures::BoundedStorable;
+
+use crate::error::FieldError;
+use crate::{Error, Event, EventPayload, EVENT_SIZE_HEADROOM, VALIDATION_LIMITS};
+
+// Upper bounds for the configurable limits
+const HARD_MAX_TITLE_LEN: u32 = 500;
+const HARD_MAX_DESCRIPTION_LEN: u32 = 8_000;
+const HARD_MAX_LOCATION_LEN: u32 = 500;
+const HARD_MAX_IMAGE_URL_LEN: u32 = 2_048;
+
+// Bytes of an event record kept for everything but its text fields, such as the
+// recurrence exceptions, principals and timestamps
+const NON_TEXT_RESERVE: u32 = 3 * 1024;
+
+// Most bytes the text fields of an event may take together
+const MAX_TEXT_BYTES: usize = (Event::MAX_SIZE - EVENT_SIZE_HEADROOM - NON_TEXT_RESERVE) as usize;
+
+#[derive(CandidType, Clone, Serialize, Deserialize)]
+pub struct ValidationLimits {
+    pub title_min_len: u32,
+    pub title_max_len: u32,
+    pub description_max_len: u32,
+    pub location_max_len: u32,
+    pub image_url_max_len: u32,
+    // Lowercase URL schemes accepted for event_card_imgurl, e.g. "https"
+    pub allowed_url_schemes: Vec<String>,
+    // Characters rejected in every text field; control characters are always
+    // rejected, except line breaks and tabs in the description
+    pub forbidden_characters: String,
+    // Whether to trim fields and collapse whitespace in the title and location
+    pub normalize_whitespace: bool,
+}
+
+impl Default for ValidationLimits {
+    fn default() -> Self {
+        Self {
+            title_min_len: 1,
+            title_max_len: 200,
+            description_max_len: 5_000,
+            location_max_len: 300,
+            image_url_max_len: 2_048,
+            allowed_url_schemes: vec!["https".to_string()],
+            forbidden_characters: "<>".to_string(),
+            normalize_whitespace: true,
+        }
+    }
+}
+
+impl ic_stable_structures::Storable for ValidationLimits {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+// The limits currently in force
+pub fn limits() -> ValidationLimits {
+    VALIDATION_LIMITS.with(|l| l.borrow().get().clone())
+}
+
+// Replaces the limits after checking that they are consistent
+pub fn set_limits(limits: ValidationLimits) -> Result<(), Error> {
+    let mut fields = Vec::new();
+    for (field, value, cap) in [
+        ("title_max_len", limits.title_max_len, HARD_MAX_TITLE_LEN),
+        ("description_max_len", limits.description_max_len, HARD_MAX_DESCRIPTION_LEN),
+        ("location_max_len", limits.location_max_len, HARD_MAX_LOCATION_LEN),
+        ("image_url_max_len", limits.image_url_max_len, HARD_MAX_IMAGE_URL_LEN),
+    ] {
+        if value > cap {
+            fields.push(field_error(field, format!("must be at most {}", cap)));
+        }
+    }
+    if limits.title_min_len == 0 || limits.title_min_len > limits.title_max_len {
+        fields.push(field_error("title_min_len", "must be between 1 and title_max_len"));
+    }
+    if limits.allowed_url_schemes.is_empty()
+        || limits
+            .allowed_url_schemes
+            .iter()
+            .any(|scheme| scheme.is_empty() || !scheme.chars().all(|c| c.is_ascii_lowercase()))
+    {
+        fields.push(field_error(
+            "allowed_url_schemes",
+            "must list at least one scheme, each in lowercase letters",
+        ));
+    }
+    if !fields.is_empty() {
+        return Err(invalid_input(fields));
+    }
+    VALIDATION_LIMITS
+        .with(|l| l.borrow_mut().set(limits))
+        .map_err(|_| Error::Internal {
+            msg: "cannot store the validation limits".to_string(),
+        })?;
+    Ok(())
+}
+
+// Normalizes the text fields of `payload` in place and checks them against the current limits
+pub fn validate(payload: &mut EventPayload) -> Result<(), Error> {
+    let limits = limits();
+    if limits.normalize_whitespace {
+        payload.event_title = collapse_whitespace(&payload.event_title);
+        payload.event_location = collapse_whitespace(&payload.event_location);
+        payload.event_description = payload.event_description.trim().to_string();
+        payload.event_card_imgurl = payload.event_card_imgurl.trim().to_string();
+    }
+
+    let mut fields = Vec::new();
+    let title_len = payload.event_title.chars().count() as u32;
+    if title_len < limits.title_min_len || title_len > limits.title_max_len {
+        fields.push(field_error(
+            "event_title",
+            format!(
+                "must be between {} and {} characters long",
+                limits.title_min_len, limits.title_max_len
+            ),
+        ));
+    }
+    check_max_len(&mut fields, "event_description", &payload.event_description, limits.description_max_len);
+    check_max_len(&mut fields, "event_location", &payload.event_location, limits.location_max_len);
+    check_max_len(&mut fields, "event_card_imgurl", &payload.event_card_imgurl, limits.image_url_max_len);
+
+    for (field, text, multiline) in [
+        ("event_title", &payload.event_title, false),
+        ("event_description", &payload.event_description, true),
+        ("event_location", &payload.event_location, false),
+        ("event_card_imgurl", &payload.event_card_imgurl, false),
+    ] {
+        if let Some(c) = text.chars().find(|&c| is_forbidden(c, multiline, &limits)) {
+            fields.push(field_error(field, format!("must not contain {:?}", c)));
+        }
+    }
+
+    // Only checked once the fields fit their own limits, and reported on the largest one
+    let texts = [
+        ("event_title", &payload.event_title),
+        ("event_description", &payload.event_description),
+        ("event_location", &payload.event_location),
+        ("event_card_imgurl", &payload.event_card_imgurl),
+    ];
+    let text_bytes: usize = texts.iter().map(|(_, text)| text.len()).sum();
+    if fields.is_empty() && text_bytes > MAX_TEXT_BYTES {
+        let (field, _) = texts.iter().max_by_key(|(_, text)| text.len()).unwrap();
+        fields.push(field_error(
+            field,
+            format!("text fields must take at most {} bytes together", MAX_TEXT_BYTES),
+        ));
+    }
+
+    // The image is optional; when given it has to be a URL with an allowed scheme
+    if !payload.event_card_imgurl.is_empty() && !is_allowed_url(&payload.event_card_imgurl, &limits) {
+        fields.push(field_error(
+            "event_card_imgurl",
+            format!("must be a URL with one of the schemes {}", limits.allowed_url_schemes.join(", ")),
+        ));
+    }
+
+    if fields.is_empty() {
+        Ok(())
+    } else {
+        Err(invalid_input(fields))
+    }
+}
+
+fn check_max_len(fields: &mut Vec<FieldError>, field: &str, text: &str, max_len: u32) {
+    if text.chars().count() as u32 > max_len {
+        fields.push(field_error(field, format!("must be at most {} characters long", max_len)));
+    }
+}
+
+// Trims `text` and replaces every run of whitespace inside it with a single space
+fn collapse_whitespace(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+fn is_forbidden(c: char, multiline: bool, limits: &ValidationLimits) -> bool {
+    if multiline && matches!(c, '\n' | '\r' | '\t') {
+        return false;
+    }
+    c.is_control() || limits.forbidden_characters.contains(c)
+}
+
+// Whether `url` looks like `<scheme>://<host>[/...]` with an allowed scheme
+fn is_allowed_url(url: &str, limits: &ValidationLimits) -> bool {
+    let Some((scheme, rest)) = url.split_once("://") else {
+        return false;
+    };
+    let host = rest.split(['/', '?', '#']).next().unwrap_or_default();
+    limits
+        .allowed_url_schemes
+        .iter()
+        .any(|allowed| allowed.eq_ignore_ascii_case(scheme))
+        && !host.is_empty()
+        && !url.chars().any(char::is_whitespace)
+}
+
+fn field_error(field: &str, msg: impl Into<String>) -> FieldError {
+    FieldError {
+        field: field.to_string(),
+        msg: msg.into(),
+    }
+}
+
+// InvalidInput error listing every offending field
+fn invalid_input(fields: Vec<FieldError>) -> Error {
+    let names: Vec<&str> = fields.iter().map(|f| f.field.as_str()).collect();
+    Error::InvalidInput {
+        msg: format!("invalid {}", names.join(", ")),
+        fields,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn payload(title: &str, description: &str, imgurl: &str) -> EventPayload {
+        EventPayload {
+            event_description: description.to_string(),
+            event_title: title.to_string(),
+            event_location: String::new(),
+            event_card_imgurl: imgurl.to_string(),
+            starts_at: 0,
+            ends_at: 0,
+            time_zone: String::new(),
+            recurrence: None,
+            capacity: None,
+        }
+    }
+
+    // Names of the fields `validate` rejects
+    fn rejected(mut payload: EventPayload) -> Vec<String> {
+        match validate(&mut payload) {
+            Ok(()) => Vec::new(),
+            Err(Error::InvalidInput { fields, .. }) => {
+                fields.into_iter().map(|field| field.field).collect()
+            }
+            Err(_) => panic!("validation should fail with InvalidInput"),
+        }
+    }
+
+    #[test]
+    fn accepts_urls_with_allowed_schemes_only() {
+        for url in ["https://example.com", "HTTPS://example.com/a.png?x=1", ""] {
+            assert!(rejected(payload("Title", "", url)).is_empty(), "{}", url);
+        }
+        let invalid = ["http://example.com", "example.com", "https://", "https:///a", "https://a b"];
+        for url in invalid {
+            assert_eq!(rejected(payload("Title", "", url)), ["event_card_imgurl"], "{}", url);
+        }
+    }
+
+    #[test]
+    fn rejects_forbidden_and_control_characters() {
+        assert_eq!(rejected(payload("<b>", "", "")), ["event_title"]);
+        assert_eq!(rejected(payload("Title", "a\u{7}b", "")), ["event_description"]);
+        // Line breaks are kept in the description only
+        assert!(rejected(payload("Title", "line\nbreak\ttab", "")).is_empty());
+        let mut location = payload("Title", "", "");
+        location.event_location = "a\u{0}b".to_string();
+        assert_eq!(rejected(location), ["event_location"]);
+    }
+
+    #[test]
+    fn normalizes_whitespace() {
+        let mut event = payload("  Launch \n  party ", "  text \n", " https://example.com ");
+        assert!(validate(&mut event).is_ok());
+        assert_eq!(event.event_title, "Launch party");
+        assert_eq!(event.event_description, "text");
+        assert_eq!(event.event_card_imgurl, "https://example.com");
+    }
+
+    #[test]
+    fn applies_configured_limits() {
+        let limits = ValidationLimits {
+            title_min_len: 3,
+            title_max_len: 5,
+            allowed_url_schemes: vec!["ipfs".to_string()],
+            forbidden_characters: "#".to_string(),
+            ..ValidationLimits::default()
+        };
+        assert!(set_limits(limits).is_ok());
+        assert!(rejected(payload("abc", "", "ipfs://cid")).is_empty());
+        assert_eq!(rejected(payload("ab", "", "")), ["event_title"]);
+        assert_eq!(rejected(payload("abcdef", "", "")), ["event_title"]);
+        assert_eq!(rejected(payload("abc", "#", "https://example.com")), [
+            "event_description",
+            "event_card_imgurl"
+        ]);
+    }
+
+    #[test]
+    fn keeps_configured_limits_within_the_hard_caps() {
+        let invalid = ValidationLimits {
+            title_min_len: 0,
+            description_max_len: HARD_MAX_DESCRIPTION_LEN + 1,
+            allowed_url_schemes: vec!["HTTPS".to_string()],
+            ..ValidationLimits::default()
+        };
+        let Err(Error::InvalidInput { fields, .. }) = set_limits(invalid) else {
+            panic!("limits should be rejected");
+        };
+        let names: Vec<&str> = fields.iter().map(|field| field.field.as_str()).collect();
+        assert_eq!(names, ["description_max_len", "title_min_len", "allowed_url_schemes"]);
+        assert_eq!(limits().description_max_len, ValidationLimits::default().description_max_len);
+    }
+
+    #[test]
+    fn caps_text_fields_in_bytes() {
+        let limits = ValidationLimits {
+            description_max_len: HARD_MAX_DESCRIPTION_LEN,
+            ..ValidationLimits::default()
+        };
+        assert!(set_limits(limits).is_ok());
+        // Within the character limit, but four bytes per character
+        let wide = "\u{1F600}".repeat(HARD_MAX_DESCRIPTION_LEN as usize);
+        assert_eq!(rejected(payload("Title", &wide, "")), ["event_description"]);
+        let narrow = "a".repeat(HARD_MAX_DESCRIPTION_LEN as usize);
+        assert!(rejected(payload("Title", &narrow, "")).is_empty());
+        // The hard caps in ASCII fit the byte budget
+        let hard_caps = [
+            HARD_MAX_TITLE_LEN,
+            HARD_MAX_DESCRIPTION_LEN,
+            HARD_MAX_LOCATION_LEN,
+            HARD_MAX_IMAGE_URL_LEN,
+        ];
+        assert!(hard_caps.iter().sum::<u32>() as usize <= MAX_TEXT_BYTES);
+    }
+}